@@ -0,0 +1,276 @@
+use crate::tmx::{search_in_tu, TmxReader};
+use crate::{
+    Error, FileFormat, HitCounts, MatchResult, Result, SearchOptions, SearchSource, SearchString,
+};
+use rayon::prelude::*;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use walkdir::WalkDir;
+
+/// One item streamed back by [`search_paths`].
+#[derive(Debug)]
+pub enum SearchEvent {
+    Match(MatchResult),
+    /// Sent after the matches of a file that was searched.
+    Finished {
+        path: PathBuf,
+        counts: HitCounts,
+    },
+    /// A file or input that could not be read. The rest of the batch goes
+    /// on.
+    Failed {
+        path: PathBuf,
+        error: Error,
+    },
+}
+
+/// Expands `inputs` into the files to search. Directories are walked
+/// recursively and keep files with a supported extension, plus `.xml`,
+/// `.txt` and extensionless files whose content is recognized; inputs
+/// containing `*`, `?` or `[` are glob patterns; anything else is taken as a
+/// file, whatever its extension.
+pub fn collect_files(inputs: &[String]) -> (Vec<PathBuf>, Vec<SearchEvent>) {
+    let mut files = Vec::new();
+    let mut failures = Vec::new();
+
+    for input in inputs {
+        if input.contains(['*', '?', '[']) {
+            match glob::glob(input) {
+                Ok(paths) => {
+                    for entry in paths {
+                        match entry {
+                            Ok(path) if path.is_dir() => walk_dir(&path, &mut files, &mut failures),
+                            Ok(path) => files.push(path),
+                            Err(e) => {
+                                let path = e.path().to_owned();
+                                let error = io_error(&path, e.into());
+                                failures.push(SearchEvent::Failed { path, error });
+                            }
+                        }
+                    }
+                }
+                Err(e) => failures.push(SearchEvent::Failed {
+                    path: PathBuf::from(input),
+                    error: io_error(
+                        Path::new(input),
+                        io::Error::new(io::ErrorKind::InvalidInput, e.msg),
+                    ),
+                }),
+            }
+        } else if Path::new(input).is_dir() {
+            walk_dir(Path::new(input), &mut files, &mut failures);
+        } else {
+            files.push(PathBuf::from(input));
+        }
+    }
+
+    files.sort();
+    files.dedup();
+    return (files, failures);
+}
+
+fn walk_dir(dir: &Path, files: &mut Vec<PathBuf>, failures: &mut Vec<SearchEvent>) {
+    for entry in WalkDir::new(dir).follow_links(true) {
+        match entry {
+            Ok(entry) => {
+                if entry.file_type().is_file() && is_supported(entry.path()) {
+                    files.push(entry.into_path());
+                }
+            }
+            Err(e) => {
+                let path = e.path().unwrap_or(dir).to_owned();
+                let error = io_error(&path, e.into());
+                failures.push(SearchEvent::Failed { path, error });
+            }
+        }
+    }
+}
+
+/// Exports often come as `.xml` or `.txt`, so those are sniffed like
+/// files without an extension.
+fn is_supported(path: &Path) -> bool {
+    if FileFormat::from_extension(path).is_some() {
+        return true;
+    }
+
+    let extension = path.extension().and_then(|e| e.to_str());
+    let sniffed =
+        extension.is_none_or(|e| e.eq_ignore_ascii_case("xml") || e.eq_ignore_ascii_case("txt"));
+    return sniffed && crate::detect_format(path).is_ok();
+}
+
+fn io_error(path: &Path, source: io::Error) -> Error {
+    return Error::Io {
+        path: path.to_str().unwrap_or_default().to_owned(),
+        source,
+    };
+}
+
+/// Searches every file under `inputs` (see [`collect_files`]) with one
+/// matcher, parsing the files in parallel on all cores. Matches are streamed
+/// back as they are found, so their order across files is not fixed.
+pub fn search_paths(
+    inputs: &[String],
+    options: SearchOptions,
+    matcher: Arc<dyn SearchString>,
+) -> impl Iterator<Item = SearchEvent> {
+    let (files, failures) = collect_files(inputs);
+    let (sender, receiver) = mpsc::channel();
+
+    for failure in failures {
+        sender.send(failure).unwrap_or_default();
+    }
+
+    thread::spawn(move || {
+        files.par_iter().for_each_with(sender, |sender, path| {
+            // a failed send means the receiver was dropped: nobody is
+            // listening any more
+            search_file(path, &options, matcher.as_ref(), &mut |event| {
+                sender.send(event).is_ok()
+            });
+        });
+    });
+
+    return receiver.into_iter();
+}
+
+/// Hands each match of one file to `send` as soon as it is found, then
+/// `Finished` or `Failed`. Stops as soon as `send` returns false.
+fn search_file(
+    path: &Path,
+    options: &SearchOptions,
+    matcher: &dyn SearchString,
+    send: &mut dyn FnMut(SearchEvent) -> bool,
+) {
+    let mut counts = HitCounts::default();
+    let mut found = |match_result: MatchResult| {
+        counts.add(&HitCounts::from_results(std::slice::from_ref(
+            &match_result,
+        )));
+        return send(SearchEvent::Match(match_result));
+    };
+
+    let searched = match crate::detect_format(path) {
+        Ok(FileFormat::Tmx) => search_tmx(path, options, matcher, &mut found),
+        Ok(_) => crate::read_file_with_parser(path).map(|parsed| {
+            parsed
+                .search_in_file(options, matcher)
+                .into_iter()
+                .all(&mut found)
+        }),
+        Err(error) => Err(error),
+    };
+
+    let event = match searched {
+        Ok(true) => SearchEvent::Finished {
+            path: path.to_owned(),
+            counts,
+        },
+        Ok(false) => return,
+        Err(error) => SearchEvent::Failed {
+            path: path.to_owned(),
+            error,
+        },
+    };
+    send(event);
+}
+
+/// Searches a TMX one unit at a time, so memories of any size are searched
+/// in constant memory. Returns false when `found` asked to stop.
+fn search_tmx(
+    path: &Path,
+    options: &SearchOptions,
+    matcher: &dyn SearchString,
+    found: &mut dyn FnMut(MatchResult) -> bool,
+) -> Result<bool> {
+    let path_str = path.to_str().unwrap_or_default();
+    let tmx_reader = TmxReader::from_path(path_str)?;
+    let src_lang = tmx_reader.header().src_lang.to_owned();
+    let from = SearchSource {
+        path: path_str,
+        format: FileFormat::Tmx,
+        file_index: 0,
+        source_language: &src_lang,
+        target_language: "",
+    };
+
+    let mut match_results = Vec::new();
+    for (unit_index, tu) in tmx_reader.enumerate() {
+        search_in_tu(
+            &from,
+            unit_index,
+            &tu?,
+            options,
+            matcher,
+            &mut match_results,
+        );
+        if !match_results.drain(..).all(&mut *found) {
+            return Ok(false);
+        }
+    }
+
+    return Ok(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect_files, search_paths, SearchEvent};
+    use crate::SearchInFile;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[test]
+    fn sniffs_xml_files_in_directories() {
+        let (files, failures) = collect_files(&["./tests".to_string()]);
+        assert!(failures.is_empty());
+        assert!(files.iter().any(|f| f.ends_with("tmx_export.xml")));
+        // only .xml, .txt and extensionless files are sniffed
+        assert!(!files.iter().any(|f| f.ends_with("package.bin")));
+    }
+
+    #[test]
+    fn searches_directories_and_reports_failures() {
+        let inputs = vec!["./tests".to_string(), "./tests/*.missing".to_string()];
+        let events = search_paths(
+            &inputs,
+            crate::SearchOptions::default(),
+            Arc::new("approval".to_string()),
+        )
+        .collect::<Vec<_>>();
+
+        let matched_files = events
+            .iter()
+            .filter_map(|e| match e {
+                SearchEvent::Match(result) => Path::new(&result.path).file_name()?.to_str(),
+                SearchEvent::Failed { .. } | SearchEvent::Finished { .. } => None,
+            })
+            .collect::<std::collections::HashSet<_>>();
+        assert!(matched_files.contains("approval.sdlxliff"));
+        assert!(matched_files.contains("approval.xlz"));
+
+        // malformed.xliff fails to parse without stopping the others
+        assert!(events.iter().any(|e| matches!(
+            e,
+            SearchEvent::Failed { path, .. } if path.ends_with("malformed.xliff")
+        )));
+
+        // TMX files are streamed unit by unit, with the same counts
+        let matcher = "account".to_string();
+        let citic = crate::tmx::TmxFile::new("./tests/CITIC.tmx").unwrap();
+        let expected = citic.count_in_file(&crate::SearchOptions::default(), &matcher);
+        assert!(expected.total > 0);
+        let events = search_paths(
+            &["./tests/CITIC.tmx".to_string()],
+            crate::SearchOptions::default(),
+            Arc::new(matcher),
+        )
+        .collect::<Vec<_>>();
+        assert!(events.iter().any(|e| matches!(
+            e,
+            SearchEvent::Finished { path, counts }
+                if path.ends_with("CITIC.tmx") && *counts == expected
+        )));
+    }
+}
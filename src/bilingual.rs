@@ -0,0 +1,210 @@
+use crate::xliff::TransUnit;
+use crate::{extract_text, MatchResult, SearchOptions, SearchSource, SearchString};
+use serde::Serialize;
+
+/// A condition on one side of a unit.
+pub struct Condition {
+    pub matcher: Box<dyn SearchString>,
+    /// The side must not match.
+    pub negated: bool,
+}
+
+impl Condition {
+    pub fn contains(matcher: impl SearchString + 'static) -> Condition {
+        return Condition {
+            matcher: Box::new(matcher),
+            negated: false,
+        };
+    }
+
+    pub fn lacks(matcher: impl SearchString + 'static) -> Condition {
+        return Condition {
+            matcher: Box::new(matcher),
+            negated: true,
+        };
+    }
+
+    pub fn holds(&self, text: &str) -> bool {
+        return self.matcher.match_string(text).is_some() != self.negated;
+    }
+}
+
+/// Checks the source and target of a unit together, e.g. "the source
+/// contains `account` but the target lacks `账户`". A side without a
+/// condition always holds.
+#[derive(Default)]
+pub struct BilingualQuery {
+    pub source: Option<Condition>,
+    pub target: Option<Condition>,
+}
+
+impl BilingualQuery {
+    pub fn holds(&self, source: &str, target: &str) -> bool {
+        return self.source.as_ref().is_none_or(|c| c.holds(source))
+            && self.target.as_ref().is_none_or(|c| c.holds(target));
+    }
+
+    /// Pairs up both sides when the query holds, with the occurrences of
+    /// the conditions that are not negated filled in.
+    pub(crate) fn check(
+        &self,
+        mut source: MatchResult,
+        mut target: MatchResult,
+    ) -> Option<BilingualMatch> {
+        if !self.holds(&source.text, &target.text) {
+            return None;
+        }
+
+        for (side, condition) in [(&mut source, &self.source), (&mut target, &self.target)] {
+            if let Some(condition) = condition.as_ref().filter(|c| !c.negated) {
+                side.find_occurrences(condition.matcher.as_ref());
+            }
+        }
+
+        return Some(BilingualMatch { source, target });
+    }
+}
+
+/// A unit for which a [`BilingualQuery`] holds. Both sides carry the same
+/// location fields.
+#[derive(Debug, Clone, Serialize)]
+pub struct BilingualMatch {
+    pub source: MatchResult,
+    pub target: MatchResult,
+}
+
+impl BilingualMatch {
+    pub(crate) fn for_each_side(&mut self, mut f: impl FnMut(&mut MatchResult)) {
+        f(&mut self.source);
+        f(&mut self.target);
+    }
+}
+
+/// Checks every unit, or every `<mrk>` segment of segmented units. The
+/// status filter and the scope of `options` apply, the scope to the target
+/// language as in TMX. Units and segments without a target are skipped, so
+/// untranslated text never satisfies a condition that the target lacks
+/// something; `<alt-trans>` candidates are not checked either.
+pub fn search_bilingual_in_transunits(
+    from: &SearchSource,
+    trans_units: &[TransUnit],
+    options: &SearchOptions,
+    query: &BilingualQuery,
+    bilingual_matches: &mut Vec<BilingualMatch>,
+) {
+    let wanted = |status| options.status.is_none_or(|s| s == status);
+    if !options.scope.includes(from.target_language, false) {
+        return;
+    }
+
+    for (unit_index, tu) in trans_units.iter().enumerate() {
+        let mut check = |source, target, mid: Option<&str>| {
+            let source = MatchResult::new(
+                from.format,
+                from.source_language,
+                extract_text(source, options.include_tags),
+                tu.source_position,
+            );
+            let target = MatchResult::new(
+                from.format,
+                from.target_language,
+                extract_text(target, options.include_tags),
+                tu.target_position,
+            );
+
+            if let Some(mut bilingual_match) = query.check(source, target) {
+                bilingual_match.for_each_side(|side| {
+                    side.path = from.path.to_owned();
+                    side.file_index = from.file_index;
+                    side.unit_index = unit_index;
+                    side.unit_id = tu.id.to_owned();
+                    side.segment_id = tu.segment_id.to_owned();
+                    side.sn = Some(tu.sn);
+                    side.mid = mid.map(str::to_owned);
+                });
+                bilingual_matches.push(bilingual_match);
+            }
+        };
+
+        let segments = tu.segments();
+        if segments.is_empty() {
+            if let Some(target) = tu.target.as_deref().filter(|_| wanted(tu.status())) {
+                check(&tu.source, target, None);
+            }
+        } else {
+            for segment in &segments {
+                if let Some(target) = segment
+                    .target
+                    .filter(|_| wanted(tu.segment_status(segment)))
+                {
+                    check(segment.source, target, Some(segment.mid));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BilingualQuery, Condition};
+    use crate::{SearchInFile, SearchOptions, SearchScope};
+
+    #[test]
+    fn checks_source_and_target_together() {
+        let t = crate::xliff::XliffFile::new("./tests/approval.sdlxliff").unwrap();
+        let query = BilingualQuery {
+            source: Some(Condition::contains("approv".to_string())),
+            target: Some(Condition::lacks("Genehmig".to_string())),
+        };
+
+        let found = t.search_bilingual(&SearchOptions::default(), &query);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].source.unit_id, "a1b2c3");
+        assert_eq!(found[0].source.mid.as_deref(), Some("1"));
+        assert_eq!(found[0].source.occurrences[0].matched, "approv");
+        assert!(found[0].target.occurrences.is_empty());
+    }
+
+    #[test]
+    fn skips_untranslated_units_and_other_languages() {
+        let t = crate::xliff::XliffFile::new("./tests/untranslated.sdlxliff").unwrap();
+        let query = BilingualQuery {
+            source: Some(Condition::contains("file".to_string())),
+            target: Some(Condition::lacks("Speichern".to_string())),
+        };
+
+        // u1 has no target yet and is not reported as lacking the term
+        let found = t.search_bilingual(&SearchOptions::default(), &query);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].source.unit_id, "u2");
+
+        let mut options = SearchOptions {
+            scope: SearchScope::Languages(vec!["fr".to_string()]),
+            ..Default::default()
+        };
+        assert!(t.search_bilingual(&options, &query).is_empty());
+        options.scope = SearchScope::Languages(vec!["de".to_string()]);
+        assert_eq!(t.search_bilingual(&options, &query).len(), 1);
+    }
+
+    #[test]
+    fn checks_every_target_language_of_tmx() {
+        let t = crate::tmx::TmxFile::new("./tests/CITIC.tmx").unwrap();
+        let options = SearchOptions::default();
+
+        let consistent = BilingualQuery {
+            source: Some(Condition::contains("account".to_string())),
+            target: Some(Condition::lacks("账户".to_string())),
+        };
+        assert!(t.search_bilingual(&options, &consistent).is_empty());
+
+        let query = BilingualQuery {
+            source: Some(Condition::contains("account".to_string())),
+            target: Some(Condition::contains("密码".to_string())),
+        };
+        let found = t.search_bilingual(&options, &query);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].target.language, "zh-cn");
+        assert_eq!(found[0].target.occurrences[0].char_span, 9..11);
+    }
+}
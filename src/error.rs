@@ -0,0 +1,300 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A location inside a parsed document: the byte offset and its 1-based
+/// line and column.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// The position of the first byte of a document.
+    pub fn start() -> Position {
+        return Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        };
+    }
+
+    /// Moves the position past `bytes`, which must directly follow it.
+    pub fn advance(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            if *byte == b'\n' {
+                self.line += 1;
+                self.column = 1;
+            } else if byte & 0xC0 != 0x80 {
+                // count characters, not UTF-8 continuation bytes
+                self.column += 1;
+            }
+        }
+        self.offset += bytes.len();
+    }
+
+    /// Advances to `offset` in `content`. Offsets before the current one
+    /// leave the position unchanged.
+    pub(crate) fn advance_to(&mut self, content: &str, offset: usize) {
+        let offset = offset.min(content.len());
+        if offset > self.offset {
+            self.advance(&content.as_bytes()[self.offset..offset]);
+        }
+    }
+
+    pub fn from_offset(content: &str, offset: usize) -> Position {
+        let offset = offset.min(content.len());
+        let before = &content.as_bytes()[..offset];
+
+        let line = before.iter().filter(|b| **b == b'\n').count() + 1;
+        let line_start = before
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map(|p| p + 1)
+            .unwrap_or(0);
+        let column = String::from_utf8_lossy(&before[line_start..])
+            .chars()
+            .count()
+            + 1;
+
+        return Position {
+            offset,
+            line,
+            column,
+        };
+    }
+
+    /// Like [`Position::from_offset`], but streams the content instead of
+    /// needing it in memory.
+    pub fn from_reader<R: Read>(reader: R, offset: usize) -> std::io::Result<Position> {
+        let mut position = Position::start();
+        let mut reader = reader.take(offset as u64);
+        let mut buf = [0; 8192];
+
+        loop {
+            let read = reader.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            position.advance(&buf[..read]);
+        }
+
+        return Ok(position);
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            return write!(f, "byte {}", self.offset);
+        }
+        write!(
+            f,
+            "line {}, column {} (byte {})",
+            self.line, self.column, self.offset
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    Write {
+        path: String,
+        source: std::io::Error,
+    },
+    Zip {
+        path: String,
+        source: zip::result::ZipError,
+    },
+    ZipEntryNotFound {
+        path: String,
+        entry: String,
+    },
+    Xlsx {
+        path: String,
+        source: calamine::XlsxError,
+    },
+    WorksheetNotFound {
+        path: String,
+    },
+    /// The header row of the worksheet lacks the source or target language.
+    LanguageColumnsNotFound {
+        path: String,
+    },
+    Xml {
+        path: String,
+        position: Position,
+        source: quick_xml::Error,
+    },
+    Attribute {
+        path: String,
+        position: Position,
+        source: quick_xml::events::attributes::AttrError,
+    },
+    UnsupportedFormat {
+        path: String,
+    },
+    WriteUnsupported {
+        path: String,
+        format: crate::FileFormat,
+    },
+    /// The file was modified after it was parsed, so edits can no longer be
+    /// spliced into it.
+    SourceChanged {
+        path: String,
+    },
+    InvalidQuery {
+        query: String,
+        offset: usize,
+        reason: String,
+    },
+}
+
+impl Error {
+    pub(crate) fn xml(offset: usize, source: quick_xml::Error) -> Error {
+        return Error::Xml {
+            path: String::new(),
+            position: Position {
+                offset,
+                ..Default::default()
+            },
+            source,
+        };
+    }
+
+    pub(crate) fn attribute(
+        offset: usize,
+        source: quick_xml::events::attributes::AttrError,
+    ) -> Error {
+        return Error::Attribute {
+            path: String::new(),
+            position: Position {
+                offset,
+                ..Default::default()
+            },
+            source,
+        };
+    }
+
+    /// Fills in the file path and resolves byte offsets into line/column
+    /// for errors raised while parsing `content`.
+    pub(crate) fn locate(self, content: &str, file_path: &str) -> Error {
+        return self.relocate(file_path, |offset| Position::from_offset(content, offset));
+    }
+
+    /// Same as `locate` for documents that were streamed from `file_path`:
+    /// the file is read again up to the error to find its line.
+    pub(crate) fn locate_in_file(self, file_path: &str) -> Error {
+        return self.relocate(file_path, |offset| {
+            File::open(file_path)
+                .and_then(|f| Position::from_reader(f, offset))
+                .unwrap_or(Position {
+                    offset,
+                    ..Default::default()
+                })
+        });
+    }
+
+    fn relocate(self, file_path: &str, position_at: impl Fn(usize) -> Position) -> Error {
+        match self {
+            Error::Xml {
+                position, source, ..
+            } => Error::Xml {
+                path: file_path.to_owned(),
+                position: position_at(position.offset),
+                source,
+            },
+            Error::Attribute {
+                position, source, ..
+            } => Error::Attribute {
+                path: file_path.to_owned(),
+                position: position_at(position.offset),
+                source,
+            },
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "Failed to read {}: {}", path, source),
+            Error::Write { path, source } => write!(f, "Failed to write {}: {}", path, source),
+            Error::Zip { path, source } => write!(f, "Invalid zip file {}: {}", path, source),
+            Error::ZipEntryNotFound { path, entry } => {
+                write!(f, "{} not found in zipped file {}", entry, path)
+            }
+            Error::Xlsx { path, source } => {
+                write!(f, "Failed to open xlsx file {}: {}", path, source)
+            }
+            Error::WorksheetNotFound { path } => write!(f, "First worksheet not found in {}", path),
+            Error::LanguageColumnsNotFound { path } => {
+                write!(
+                    f,
+                    "No source and target language in the header row of {}",
+                    path
+                )
+            }
+            Error::Xml {
+                path,
+                position,
+                source,
+            } => write!(f, "Error in {} at {}: {}", path, position, source),
+            Error::Attribute {
+                path,
+                position,
+                source,
+            } => write!(
+                f,
+                "Malformed attribute in {} at {}: {}",
+                path, position, source
+            ),
+            Error::UnsupportedFormat { path } => write!(f, "Unsupported file type: {}", path),
+            Error::WriteUnsupported { path, format } => {
+                write!(f, "Writing {} files is not supported: {}", format, path)
+            }
+            Error::SourceChanged { path } => {
+                write!(f, "{} changed on disk since it was parsed", path)
+            }
+            Error::InvalidQuery {
+                query,
+                offset,
+                reason,
+            } => write!(
+                f,
+                "Invalid query {:?} at byte {}: {}",
+                query, offset, reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Write { source, .. } => Some(source),
+            Error::Zip { source, .. } => Some(source),
+            Error::Xlsx { source, .. } => Some(source),
+            Error::Xml { source, .. } => Some(source),
+            Error::Attribute { source, .. } => Some(source),
+            Error::ZipEntryNotFound { .. }
+            | Error::WorksheetNotFound { .. }
+            | Error::LanguageColumnsNotFound { .. }
+            | Error::UnsupportedFormat { .. }
+            | Error::WriteUnsupported { .. }
+            | Error::SourceChanged { .. }
+            | Error::InvalidQuery { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{Error, Result};
+use quick_xml::events::Event;
+use quick_xml::reader::Reader;
+use serde::Serialize;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const SNIFF_LEN: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    Xliff,
+    Xlz,
+    Mqxlz,
+    Sdlppx,
+    Tmx,
+    Tbx,
+    Xlsx,
+}
+
+impl FileFormat {
+    pub fn from_extension(path: &Path) -> Option<FileFormat> {
+        let extension = path
+            .extension()
+            .unwrap_or_default()
+            .to_str()
+            .unwrap_or_default()
+            .to_lowercase();
+
+        let format = match extension.as_str() {
+            "xliff" | "xlf" | "txlf" | "sdlxliff" | "mxliff" | "mqxliff" => FileFormat::Xliff,
+            "xlz" => FileFormat::Xlz,
+            "mqxlz" => FileFormat::Mqxlz,
+            "sdlppx" => FileFormat::Sdlppx,
+            "tmx" => FileFormat::Tmx,
+            "tbx" => FileFormat::Tbx,
+            "xlsx" => FileFormat::Xlsx,
+            _ => return None,
+        };
+
+        return Some(format);
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileFormat::Xliff => "xliff",
+            FileFormat::Xlz => "xlz",
+            FileFormat::Mqxlz => "mqxlz",
+            FileFormat::Sdlppx => "sdlppx",
+            FileFormat::Tmx => "tmx",
+            FileFormat::Tbx => "tbx",
+            FileFormat::Xlsx => "xlsx",
+        }
+    }
+}
+
+impl fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Detects the format of `path` from its content, falling back to the file
+/// extension only when the content is inconclusive.
+pub fn detect_format(path: &Path) -> Result<FileFormat> {
+    let path_str = path.to_str().unwrap_or_default();
+    let io_error = |e| Error::Io {
+        path: path_str.to_owned(),
+        source: e,
+    };
+
+    let mut head = Vec::new();
+    File::open(path)
+        .map_err(io_error)?
+        .take(SNIFF_LEN)
+        .read_to_end(&mut head)
+        .map_err(io_error)?;
+
+    let sniffed = if head.starts_with(ZIP_MAGIC) {
+        sniff_zip(path)?
+    } else {
+        sniff_xml(&head)
+    };
+
+    return sniffed
+        .or_else(|| FileFormat::from_extension(path))
+        .ok_or(Error::UnsupportedFormat {
+            path: path_str.to_owned(),
+        });
+}
+
+fn sniff_zip(path: &Path) -> Result<Option<FileFormat>> {
+    let path_str = path.to_str().unwrap_or_default();
+
+    let zipped_file = File::open(path).map_err(|e| Error::Io {
+        path: path_str.to_owned(),
+        source: e,
+    })?;
+    let archive = zip::ZipArchive::new(zipped_file).map_err(|e| Error::Zip {
+        path: path_str.to_owned(),
+        source: e,
+    })?;
+    let names = archive.file_names().collect::<Vec<_>>();
+
+    if names.contains(&"[Content_Types].xml") && names.iter().any(|n| n.starts_with("xl/")) {
+        return Ok(Some(FileFormat::Xlsx));
+    }
+    if names.contains(&"content.xlf") {
+        return Ok(Some(FileFormat::Xlz));
+    }
+    if names.contains(&"document.mqxliff") {
+        return Ok(Some(FileFormat::Mqxlz));
+    }
+    if names.iter().any(|n| n.ends_with(".sdlxliff")) {
+        return Ok(Some(FileFormat::Sdlppx));
+    }
+
+    return Ok(None);
+}
+
+fn sniff_xml(head: &[u8]) -> Option<FileFormat> {
+    let head = head.strip_prefix(UTF8_BOM).unwrap_or(head);
+    let mut reader = Reader::from_reader(head);
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => {
+                let format = match e.local_name().as_ref() {
+                    b"xliff" => FileFormat::Xliff,
+                    b"tmx" => FileFormat::Tmx,
+                    b"martif" | b"tbx" => FileFormat::Tbx,
+                    _ => return None,
+                };
+                return Some(format);
+            }
+            Ok(Event::Eof) | Err(..) => return None,
+            _ => (),
+        }
+        buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_format, FileFormat};
+    use std::path::Path;
+
+    #[test]
+    fn detects_by_root_element() {
+        let detected = |p: &str| detect_format(Path::new(p)).unwrap();
+
+        assert_eq!(detected("./tests/CITIC.tmx"), FileFormat::Tmx);
+        assert_eq!(detected("./tests/lancom.tbx"), FileFormat::Tbx);
+        assert_eq!(detected("./tests/approval.sdlxliff"), FileFormat::Xliff);
+        assert_eq!(detected("./tests/tmx_export.xml"), FileFormat::Tmx);
+    }
+
+    #[test]
+    fn detects_zip_packages_by_entries() {
+        let detected = |p: &str| detect_format(Path::new(p)).unwrap();
+
+        assert_eq!(detected("./tests/approval.xlz"), FileFormat::Xlz);
+        assert_eq!(detected("./tests/package.bin"), FileFormat::Sdlppx);
+
+        let package = crate::read_file_with_parser(Path::new("./tests/package.bin")).unwrap();
+        assert_eq!(package.get_format(), FileFormat::Sdlppx);
+        assert_eq!(package.get_meta().languages.get("de-de"), Some(&2));
+    }
+}
@@ -0,0 +1,328 @@
+use crate::matcher::FoldedText;
+use crate::{MatchOptions, SearchString};
+use std::any::Any;
+use std::borrow::Cow;
+use std::ops::Range;
+
+/// How close a text has to be to the pattern of a [`FuzzyMatcher`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fuzziness {
+    /// Substrings at most `max_edits` character insertions, deletions or
+    /// substitutions away from the pattern (Levenshtein distance). With
+    /// `transpositions`, swapping two adjacent characters is one edit too
+    /// (Damerau distance).
+    Edits {
+        max_edits: usize,
+        transpositions: bool,
+    },
+    /// Whole texts whose words are at least `min_similarity` percent
+    /// similar to the pattern, the way CAT tools rate TM matches.
+    Similarity { min_similarity: u8 },
+}
+
+/// An approximate matcher that compares texts folded by its
+/// [`MatchOptions`]. The similarity of each hit to the pattern is reported
+/// in [`crate::MatchResult::similarity`].
+#[derive(Debug, Clone)]
+pub struct FuzzyMatcher {
+    pattern: Vec<char>,
+    tokens: Vec<String>,
+    fuzziness: Fuzziness,
+    options: MatchOptions,
+}
+
+/// One approximate match of the pattern.
+struct Hit {
+    start: usize,
+    end: usize,
+    edits: usize,
+}
+
+impl FuzzyMatcher {
+    pub fn new(pattern: &str, fuzziness: Fuzziness, options: MatchOptions) -> FuzzyMatcher {
+        let pattern = options.fold(pattern);
+        return FuzzyMatcher {
+            pattern: pattern.chars().collect(),
+            tokens: tokenize(&pattern),
+            fuzziness,
+            options,
+        };
+    }
+
+    fn fold<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.options.is_exact() {
+            return Cow::Borrowed(text);
+        }
+        return Cow::Owned(self.options.fold(text));
+    }
+
+    /// Non-overlapping substrings of `text` within the edit threshold, the
+    /// closest of any overlapping candidates winning. Offsets are in bytes.
+    fn find_hits(&self, text: &str, max_edits: usize, transpositions: bool) -> Vec<Hit> {
+        let pattern = &self.pattern;
+        if pattern.is_empty() {
+            return Vec::new();
+        }
+
+        // each cell holds (edits, start) of the best alignment of a pattern
+        // prefix ending at the current text position; a match may start
+        // anywhere, so row 0 costs nothing
+        let rows = pattern.len() + 1;
+        let column = |start| (0..rows).map(|i| (i, start)).collect::<Vec<_>>();
+        let mut before_previous: Vec<(usize, usize)> = column(0);
+        let mut previous = column(0);
+        let mut previous_char = None;
+
+        let mut candidates = Vec::new();
+        for (offset, c) in text.char_indices() {
+            let mut current = column(offset);
+            current[0] = (0, offset + c.len_utf8());
+            for i in 1..rows {
+                let (edits, start) = previous[i - 1];
+                let mut best = (edits + usize::from(pattern[i - 1] != c), start);
+                best = best.min((previous[i].0 + 1, previous[i].1));
+                best = best.min((current[i - 1].0 + 1, current[i - 1].1));
+                if transpositions
+                    && i > 1
+                    && Some(pattern[i - 1]) == previous_char
+                    && pattern[i - 2] == c
+                {
+                    let (edits, start) = before_previous[i - 2];
+                    best = best.min((edits + 1, start));
+                }
+                current[i] = best;
+            }
+
+            let (edits, start) = current[rows - 1];
+            let end = offset + c.len_utf8();
+            if edits <= max_edits && start < end {
+                candidates.push(Hit { start, end, edits });
+            }
+
+            before_previous = std::mem::replace(&mut previous, current);
+            previous_char = Some(c);
+        }
+
+        let mut hits: Vec<Hit> = Vec::new();
+        let mut best: Option<Hit> = None;
+        for candidate in candidates {
+            if hits.last().is_some_and(|last| candidate.start < last.end) {
+                continue;
+            }
+            match &best {
+                Some(b) if candidate.start < b.end => {
+                    if candidate.edits < b.edits {
+                        best = Some(candidate);
+                    }
+                }
+                _ => hits.extend(best.replace(candidate)),
+            }
+        }
+        hits.extend(best);
+
+        return hits;
+    }
+
+    /// Percentage of words shared by the pattern and `text`, by token edit
+    /// distance; 0 when `text` has no words.
+    fn token_similarity(&self, text: &str) -> u8 {
+        let tokens = tokenize(text);
+        let longest = tokens.len().max(self.tokens.len());
+        if tokens.is_empty() || longest == 0 {
+            return 0;
+        }
+        let distance = edit_distance(&self.tokens, &tokens);
+        return (100 * (longest - distance) / longest) as u8;
+    }
+}
+
+impl SearchString for FuzzyMatcher {
+    fn find_matches(&self, text: &str) -> Vec<Range<usize>> {
+        match self.fuzziness {
+            Fuzziness::Edits {
+                max_edits,
+                transpositions,
+            } => {
+                if self.options.is_exact() {
+                    return self
+                        .find_hits(text, max_edits, transpositions)
+                        .into_iter()
+                        .map(|hit| hit.start..hit.end)
+                        .collect();
+                }
+                let folded = FoldedText::new(text, &self.options);
+                return folded.original_spans(
+                    self.find_hits(&folded.folded, max_edits, transpositions)
+                        .into_iter()
+                        .map(|hit| hit.start..hit.end),
+                );
+            }
+            Fuzziness::Similarity { min_similarity } => {
+                if self.token_similarity(&self.fold(text)) >= min_similarity.max(1) {
+                    let whole = 0..text.len();
+                    return vec![whole];
+                }
+                return Vec::new();
+            }
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    /// For edit thresholds, the similarity of the closest substring.
+    fn similarity(&self, text: &str) -> Option<u8> {
+        match self.fuzziness {
+            Fuzziness::Edits {
+                max_edits,
+                transpositions,
+            } => {
+                let text = self.fold(text);
+                return self
+                    .find_hits(&text, max_edits, transpositions)
+                    .iter()
+                    .map(|hit| {
+                        let longest = text[hit.start..hit.end]
+                            .chars()
+                            .count()
+                            .max(self.pattern.len());
+                        (100 * (longest - hit.edits) / longest) as u8
+                    })
+                    .max();
+            }
+            Fuzziness::Similarity { .. } => {
+                return Some(self.token_similarity(&self.fold(text)));
+            }
+        }
+    }
+}
+
+/// Words, with each CJK character and each punctuation mark a word of its
+/// own; whitespace separates words and is dropped.
+pub(crate) fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+
+    for c in text.chars() {
+        if c.is_alphanumeric() && !is_cjk(c) {
+            word.push(c);
+            continue;
+        }
+        if !word.is_empty() {
+            tokens.push(std::mem::take(&mut word));
+        }
+        if !c.is_whitespace() {
+            tokens.push(c.to_string());
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+
+    return tokens;
+}
+
+fn is_cjk(c: char) -> bool {
+    return matches!(c,
+        '\u{3040}'..='\u{30FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{AC00}'..='\u{D7AF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{2FA1F}');
+}
+
+/// Levenshtein distance between two sequences.
+pub(crate) fn edit_distance<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, x) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            current[j + 1] = (previous[j] + usize::from(x != y))
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    return previous[b.len()];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fuzziness, FuzzyMatcher};
+    use crate::{MatchOptions, SearchInFile, SearchOptions, SearchString};
+
+    #[test]
+    fn finds_typo_variants() {
+        let levenshtein = FuzzyMatcher::new(
+            "acount",
+            Fuzziness::Edits {
+                max_edits: 1,
+                transpositions: false,
+            },
+            MatchOptions::default(),
+        );
+        let text = "an account, an acocunt and an amendment";
+        assert_eq!(levenshtein.find_matches(text), vec![3..10, 15..22]);
+        assert_eq!(levenshtein.similarity("your acount"), Some(100));
+        assert_eq!(levenshtein.similarity("your account"), Some(85));
+
+        let edits = |transpositions| Fuzziness::Edits {
+            max_edits: 1,
+            transpositions,
+        };
+        let damerau = FuzzyMatcher::new("account", edits(true), MatchOptions::default());
+        assert_eq!(damerau.find_matches("an acocunt"), vec![3..10]);
+        let levenshtein = FuzzyMatcher::new("account", edits(false), MatchOptions::default());
+        assert!(levenshtein.find_matches("an acocunt").is_empty());
+    }
+
+    #[test]
+    fn folds_texts_before_comparing() {
+        let options = MatchOptions {
+            ignore_case: true,
+            strip_diacritics: true,
+            ..Default::default()
+        };
+        let edits = Fuzziness::Edits {
+            max_edits: 1,
+            transpositions: false,
+        };
+        let matcher = FuzzyMatcher::new("resume", edits, options);
+        // spans point into the original, unfolded text
+        assert_eq!(matcher.find_matches("Le RÉSUMÉ"), vec![3..11]);
+        assert_eq!(matcher.similarity("Le RÉSUMÉ"), Some(100));
+        let exact = FuzzyMatcher::new("resume", edits, MatchOptions::default());
+        assert!(exact.find_matches("Le RÉSUMÉ").is_empty());
+
+        let similarity = Fuzziness::Similarity {
+            min_similarity: 100,
+        };
+        let matcher = FuzzyMatcher::new("Café crème", similarity, options);
+        assert_eq!(matcher.find_matches("CAFE CREME"), vec![0..10]);
+    }
+
+    #[test]
+    fn rates_units_by_word_similarity() {
+        let t = crate::tmx::TmxFile::new("./tests/CITIC.tmx").unwrap();
+        let matcher = FuzzyMatcher::new(
+            "Please keep your password safe & secret.",
+            Fuzziness::Similarity { min_similarity: 75 },
+            MatchOptions::default(),
+        );
+
+        let found = t.search_in_file(&SearchOptions::default(), &matcher);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].language, "en-us");
+        assert_eq!(found[0].similarity, Some(88));
+        assert_eq!(found[0].occurrences[0].span, 0..found[0].text.len());
+        assert_eq!(
+            t.count_in_file(&SearchOptions::default(), &matcher).total,
+            1
+        );
+    }
+}
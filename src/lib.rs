@@ -1,53 +1,246 @@
+#[cfg(feature = "batch")]
+pub mod batch;
+pub mod bilingual;
+pub mod error;
+pub mod format;
+pub mod fuzzy;
+pub mod matcher;
+pub mod query;
 pub mod tbx;
+pub mod tm;
 pub mod tmx;
 pub mod xliff;
 pub mod xlsx;
 
 use fancy_regex::Regex;
-use quick_xml::escape::unescape;
+use quick_xml::escape::{escape, partial_escape};
 use quick_xml::events::BytesStart;
 use quick_xml::events::Event;
 use quick_xml::reader::Reader;
 use serde::{Deserialize, Serialize};
 use std::any::Any;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
-use std::process::exit;
+use std::io::{BufRead, Read};
+use std::ops::Range;
+use std::path::Path;
+
+pub use bilingual::{BilingualMatch, BilingualQuery, Condition};
+pub use error::{Error, Position, Result};
+pub use format::{detect_format, FileFormat};
+pub use fuzzy::{Fuzziness, FuzzyMatcher};
+pub use matcher::{MatchOptions, Normalization, TextMatcher};
 
 pub struct MetaInfo<'a> {
     languages: HashMap<&'a str, usize>,
+    /// Only bilingual formats (XLIFF, XLSX) have a translation status.
+    status: Option<xliff::StatusCounts>,
+}
+
+impl<'a> MetaInfo<'a> {
+    pub fn languages(&self) -> &HashMap<&'a str, usize> {
+        return &self.languages;
+    }
+
+    pub fn status(&self) -> Option<xliff::StatusCounts> {
+        return self.status;
+    }
 }
 
 impl<'a> fmt::Display for MetaInfo<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for k in self.languages.keys() {
-            write!(
+            writeln!(
                 f,
-                "{} with {} entries\n",
+                "{} with {} entries",
                 k,
                 self.languages.get(k).unwrap_or(&0)
             )
             .unwrap_or_default()
         }
+        if let Some(status) = &self.status {
+            writeln!(
+                f,
+                "{} translated, {} untranslated, {} locked",
+                status.translated, status.untranslated, status.locked
+            )
+            .unwrap_or_default()
+        }
         Ok(())
     }
 }
 
+/// One match and where it was found.
+#[derive(Debug, Clone, Serialize)]
 pub struct MatchResult {
+    pub path: String,
+    pub format: FileFormat,
+    /// Index of the `<file>` in XLIFF documents, 0 for other formats.
+    pub file_index: usize,
+    /// Index of the unit, TMX `<tu>` or TBX entry within its file.
+    pub unit_index: usize,
+    pub unit_id: String,
+    /// `id` of the matched XLIFF 2.x `<segment>`.
+    pub segment_id: Option<String>,
+    /// Sequence number of XLIFF and XLSX units.
+    pub sn: Option<u32>,
+    /// `mid` of the matched `<mrk mtype="seg">` in segmented units.
+    pub mid: Option<String>,
+    /// Index into `alt_trans` of the unit when a candidate matched.
+    pub alt_trans: Option<usize>,
+    /// Language of the matched text.
+    pub language: String,
     pub text: String,
+    /// Every match in `text`, in order.
+    pub occurrences: Vec<Occurrence>,
+    /// How similar `text` is to the pattern of a fuzzy search, in percent.
+    pub similarity: Option<u8>,
+    /// Where the matched text starts in the file; for XLSX the line is the
+    /// row and the column the cell.
+    pub position: Position,
+    /// The texts of the other languages of the unit.
+    pub other_languages: BTreeMap<String, Vec<String>>,
+}
+
+/// One match inside the text of a [`MatchResult`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Occurrence {
     pub matched: String,
-    pub extra: Vec<String>,
+    /// Byte offsets in the text.
+    pub span: Range<usize>,
+    /// Character offsets in the text, e.g. for highlighting.
+    pub char_span: Range<usize>,
+}
+
+impl MatchResult {
+    /// A result for `text` without any occurrences yet.
+    pub(crate) fn new(
+        format: FileFormat,
+        language: &str,
+        text: String,
+        position: Position,
+    ) -> MatchResult {
+        return MatchResult {
+            path: String::new(),
+            format,
+            file_index: 0,
+            unit_index: 0,
+            unit_id: String::new(),
+            segment_id: None,
+            sn: None,
+            mid: None,
+            alt_trans: None,
+            language: language.to_owned(),
+            text,
+            occurrences: Vec::new(),
+            similarity: None,
+            position,
+            other_languages: BTreeMap::new(),
+        };
+    }
+
+    /// Fills `occurrences` with every match of `matcher` in `text`.
+    pub(crate) fn find_occurrences(&mut self, matcher: &dyn SearchString) {
+        let spans = matcher.find_matches(&self.text);
+        self.set_occurrences(spans);
+        self.similarity = matcher.similarity(&self.text);
+    }
+
+    /// Fills `occurrences` from byte ranges in `text`, in order.
+    pub(crate) fn set_occurrences(&mut self, spans: Vec<Range<usize>>) {
+        let text = &self.text;
+        let mut counted = 0;
+        let mut chars = 0;
+
+        self.occurrences.clear();
+        for span in spans {
+            chars += text[counted..span.start].chars().count();
+            let char_start = chars;
+            chars += text[span.clone()].chars().count();
+            counted = span.end;
+
+            self.occurrences.push(Occurrence {
+                matched: text[span.clone()].to_owned(),
+                span,
+                char_span: char_start..chars,
+            });
+        }
+    }
+}
+
+/// Number of matches in a file, also split up by language.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct HitCounts {
+    pub total: usize,
+    pub languages: BTreeMap<String, usize>,
+}
+
+impl HitCounts {
+    /// Sums the occurrences the search reported, so matchers that look at
+    /// the whole unit are counted the same way they matched.
+    pub fn from_results(match_results: &[MatchResult]) -> HitCounts {
+        let mut counts = HitCounts::default();
+
+        for match_result in match_results {
+            let hits = match_result.occurrences.len();
+            counts.total += hits;
+            *counts
+                .languages
+                .entry(match_result.language.to_owned())
+                .or_default() += hits;
+        }
+
+        return counts;
+    }
+
+    pub fn add(&mut self, other: &HitCounts) {
+        self.total += other.total;
+        for (language, hits) in &other.languages {
+            *self.languages.entry(language.to_owned()).or_default() += hits;
+        }
+    }
+}
+
+impl fmt::Display for HitCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.total)?;
+        let languages = self
+            .languages
+            .iter()
+            .map(|(language, hits)| format!("{}: {}", language, hits))
+            .collect::<Vec<_>>();
+        if !languages.is_empty() {
+            write!(f, " ({})", languages.join(", "))?;
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for MatchResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}: \"{}\"\n", self.matched, self.text).unwrap_or_default();
-        write!(f, "Extra info:\n").unwrap_or_default();
-        for e in &self.extra {
-            write!(f, "\t\"{}\"\n", e).unwrap_or_default();
+        write!(f, "{}", self.path)?;
+        if self.position.line != 0 {
+            write!(f, ":{}:{}", self.position.line, self.position.column)?;
+        }
+        write!(f, ": {}", self.unit_id)?;
+        if let Some(segment_id) = &self.segment_id {
+            write!(f, " (segment {})", segment_id)?;
+        }
+        if let Some(mid) = &self.mid {
+            write!(f, " (mid {})", mid)?;
+        }
+        if let Some(alt_trans) = self.alt_trans {
+            write!(f, " (alt-trans {})", alt_trans)?;
+        }
+        if let Some(similarity) = self.similarity {
+            write!(f, " ({}%)", similarity)?;
+        }
+        writeln!(f, " [{}] \"{}\"", self.language, self.text)?;
+        for (language, texts) in &self.other_languages {
+            for text in texts {
+                writeln!(f, "\t[{}] \"{}\"", language, text)?;
+            }
         }
         Ok(())
     }
@@ -55,26 +248,49 @@ impl fmt::Display for MatchResult {
 
 pub trait GetMeta {
     fn get_filename(&self) -> String;
-    fn get_meta(&self) -> MetaInfo;
+    fn get_format(&self) -> FileFormat;
+    fn get_meta(&self) -> MetaInfo<'_>;
 }
 
-pub trait SearchString {
-    fn match_string<'a>(&'a self, text: &'a str) -> Option<String>;
-    fn count_matches<'a>(&'a self, text: &'a str) -> usize;
+pub trait SearchString: Send + Sync {
+    /// Byte ranges of all non-overlapping matches in `text`.
+    fn find_matches(&self, text: &str) -> Vec<Range<usize>>;
     fn as_any(&self) -> &dyn Any;
-}
 
-impl SearchString for String {
     fn match_string<'a>(&'a self, text: &'a str) -> Option<String> {
-        if let Some(_) = text.find(self) {
-            return Some(self.to_string());
-        } else {
-            return None;
-        }
+        return self
+            .find_matches(text)
+            .first()
+            .map(|range| text[range.clone()].to_owned());
+    }
+
+    /// Whether a unit is a hit at all. By default one of its texts has to
+    /// match; see [`query::Query`] for matchers that look at more.
+    fn accepts_unit(&self, unit: &UnitView) -> bool {
+        return unit
+            .sides
+            .iter()
+            .any(|side| !self.find_matches(side.text).is_empty());
+    }
+
+    /// The matches to report in one side of an accepted unit.
+    fn find_matches_in(&self, side: &Side) -> Vec<Range<usize>> {
+        return self.find_matches(side.text);
+    }
+
+    /// How similar `text` is to the pattern in percent, for matchers that
+    /// match approximately.
+    fn similarity(&self, _text: &str) -> Option<u8> {
+        return None;
     }
+}
 
-    fn count_matches<'a>(&'a self, text: &'a str) -> usize {
-        return text.matches(self).count();
+impl SearchString for String {
+    fn find_matches(&self, text: &str) -> Vec<Range<usize>> {
+        return text
+            .match_indices(self.as_str())
+            .map(|(start, matched)| start..start + matched.len())
+            .collect();
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -83,54 +299,305 @@ impl SearchString for String {
 }
 
 impl SearchString for Regex {
-    fn match_string<'a>(&'a self, text: &'a str) -> Option<String> {
-        if let Some(first_match) = self.find(text).unwrap_or_default() {
-            return Some(first_match.as_str().to_string());
-        } else {
-            return None;
+    fn find_matches(&self, text: &str) -> Vec<Range<usize>> {
+        return self
+            .find_iter(text)
+            .filter_map(|m| m.ok())
+            .map(|m| m.range())
+            .collect();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    /// Also match the native code inside inline tags.
+    pub include_tags: bool,
+    /// Also search XLIFF `<alt-trans>` candidates.
+    pub include_alt_trans: bool,
+    /// Only search units with this status. Ignored by TMX and TBX.
+    pub status: Option<xliff::UnitStatus>,
+    /// Only search TMX units carrying all of these `<prop>`s, on the `<tu>`
+    /// or on one of its `<tuv>`s.
+    pub props: Vec<tmx::Prop>,
+    /// Only search TMX units last changed at or after this date, given as
+    /// `20220315T081502Z`, `2022-03-15` or any prefix of them.
+    pub changed_after: Option<String>,
+    /// Only search TMX units last changed before this date.
+    pub changed_before: Option<String>,
+    /// Which sides or languages of each unit are searched.
+    pub scope: SearchScope,
+}
+
+/// Restricts a search to one side or to some languages of each unit. In
+/// TMX the source is the `srclang` of the unit or of the header; termbases
+/// have no direction, so there the first language of each entry counts as
+/// the source.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SearchScope {
+    #[default]
+    All,
+    Source,
+    Target,
+    /// BCP-47 language ranges: `de` matches `de`, `de-DE` and `de-AT`, case
+    /// insensitively.
+    Languages(Vec<String>),
+}
+
+impl SearchScope {
+    pub fn includes(&self, language: &str, is_source: bool) -> bool {
+        match self {
+            SearchScope::All => true,
+            SearchScope::Source => is_source,
+            SearchScope::Target => !is_source,
+            SearchScope::Languages(ranges) => ranges.iter().any(|r| language_matches(r, language)),
         }
     }
+}
 
-    fn count_matches<'a>(&'a self, text: &'a str) -> usize {
-        return self.find_iter(text).count();
+/// Basic filtering from RFC 4647: the range equals the tag or is a prefix of
+/// it ending at a subtag boundary.
+pub fn language_matches(range: &str, language: &str) -> bool {
+    if range == "*" {
+        return true;
     }
 
-    fn as_any(&self) -> &dyn Any {
-        self
+    let range = range.to_lowercase();
+    let language = language.to_lowercase();
+    return language == range || language.starts_with(&format!("{}-", range));
+}
+
+/// The file a set of units comes from, copied into each of their matches.
+pub struct SearchSource<'a> {
+    pub path: &'a str,
+    pub format: FileFormat,
+    pub file_index: usize,
+    pub source_language: &'a str,
+    pub target_language: &'a str,
+}
+
+/// One text of a unit as a matcher sees it.
+pub struct Side<'a> {
+    pub language: &'a str,
+    pub is_source: bool,
+    pub text: &'a str,
+}
+
+/// What a matcher sees of a unit: its texts within the search scope and
+/// some metadata.
+pub struct UnitView<'a> {
+    pub id: &'a str,
+    pub sides: Vec<Side<'a>>,
+    pub notes: Vec<&'a str>,
+    /// XLIFF `state`, SDL confirmation level or memoQ status.
+    pub state: Option<&'a str>,
+}
+
+/// The sides of `view` to report, by index, with the matches in each. A
+/// matcher that accepts the unit without matching any of its texts, like a
+/// query on `id:` alone, gets the first side reported without occurrences.
+pub(crate) fn match_unit(
+    matcher: &dyn SearchString,
+    view: &UnitView,
+) -> Vec<(usize, Vec<Range<usize>>)> {
+    if view.sides.is_empty() || !matcher.accepts_unit(view) {
+        return Vec::new();
+    }
+
+    let mut found = view
+        .sides
+        .iter()
+        .map(|side| matcher.find_matches_in(side))
+        .enumerate()
+        .filter(|(_, spans)| !spans.is_empty())
+        .collect::<Vec<_>>();
+    if found.is_empty() {
+        found.push((0, Vec::new()));
     }
+
+    return found;
 }
 
 pub fn search_in_transunits(
-    trans_units: &Vec<xliff::TransUnit>,
-    include_tags: bool,
-    matcher: &Box<dyn SearchString>,
+    from: &SearchSource,
+    trans_units: &[xliff::TransUnit],
+    options: &SearchOptions,
+    matcher: &dyn SearchString,
     match_results: &mut Vec<MatchResult>,
 ) {
-    for tu in trans_units {
-        let source = extract_text(&tu.source, include_tags);
-        let target = extract_text(&tu.target, include_tags);
-        if let Some(match_result) = matcher.match_string(&source) {
-            match_results.push(MatchResult {
-                text: source,
-                matched: match_result,
-                extra: vec![tu.id.clone(), target],
-            });
-        } else if let Some(match_result) = matcher.match_string(&target) {
-            match_results.push(MatchResult {
-                text: target,
-                matched: match_result,
-                extra: vec![tu.id.clone(), source],
-            })
+    let wanted = |status| options.status.is_none_or(|s| s == status);
+    let texts = |source, target| {
+        (
+            extract_text(source, options.include_tags),
+            extract_text(target, options.include_tags),
+        )
+    };
+
+    for (unit_index, tu) in trans_units.iter().enumerate() {
+        let first = match_results.len();
+        let unit_sides = |source, target| {
+            let (source, target) = texts(source, target);
+            return [
+                (from.source_language, source, tu.source_position),
+                (from.target_language, target, tu.target_position),
+            ];
+        };
+        let state = tu
+            .state
+            .as_ref()
+            .map(|s| s.as_str())
+            .or_else(|| tu.memoq.as_ref()?.status.as_ref().map(|s| s.as_str()));
+
+        // segmented SDLXLIFF units are searched segment by segment
+        let segments = tu.segments();
+        if segments.is_empty() {
+            if wanted(tu.status()) {
+                let sides = unit_sides(&tu.source, tu.target.as_deref().unwrap_or_default());
+                match_pair(from, tu, options, matcher, sides, state, match_results);
+            }
+        } else {
+            for segment in &segments {
+                if wanted(tu.segment_status(segment)) {
+                    let sides = unit_sides(segment.source, segment.target.unwrap_or_default());
+                    let conf = segment
+                        .sdl
+                        .and_then(|s| s.conf.as_ref())
+                        .map(|c| c.as_str());
+                    let before = match_results.len();
+                    match_pair(
+                        from,
+                        tu,
+                        options,
+                        matcher,
+                        sides,
+                        conf.or(state),
+                        match_results,
+                    );
+                    for match_result in &mut match_results[before..] {
+                        match_result.mid = Some(segment.mid.to_owned());
+                    }
+                }
+            }
+        }
+
+        if options.include_alt_trans && wanted(tu.status()) {
+            for (i, alt_trans) in tu.alt_trans.iter().enumerate() {
+                let (source, target) = texts(&alt_trans.source, &alt_trans.target);
+                let sides = [
+                    (
+                        alt_trans
+                            .source_language
+                            .as_deref()
+                            .unwrap_or(from.source_language),
+                        source,
+                        alt_trans.source_position,
+                    ),
+                    (
+                        alt_trans
+                            .target_language
+                            .as_deref()
+                            .unwrap_or(from.target_language),
+                        target,
+                        alt_trans.target_position,
+                    ),
+                ];
+                let before = match_results.len();
+                match_pair(from, tu, options, matcher, sides, state, match_results);
+                for match_result in &mut match_results[before..] {
+                    match_result.alt_trans = Some(i);
+                }
+            }
+        }
+
+        for match_result in &mut match_results[first..] {
+            match_result.path = from.path.to_owned();
+            match_result.file_index = from.file_index;
+            match_result.unit_index = unit_index;
+            match_result.unit_id = tu.id.to_owned();
+            match_result.segment_id = tu.segment_id.to_owned();
+            match_result.sn = Some(tu.sn);
         }
     }
 }
 
+/// Matches the source and the target of one segment, given as language,
+/// text and position; each side that matches gets its own result, with the
+/// other side as context.
+fn match_pair(
+    from: &SearchSource,
+    tu: &xliff::TransUnit,
+    options: &SearchOptions,
+    matcher: &dyn SearchString,
+    sides: [(&str, String, Position); 2],
+    state: Option<&str>,
+    match_results: &mut Vec<MatchResult>,
+) {
+    let in_scope = (0..sides.len())
+        .filter(|&i| options.scope.includes(sides[i].0, i == 0))
+        .collect::<Vec<_>>();
+
+    let view = UnitView {
+        id: &tu.id,
+        sides: in_scope
+            .iter()
+            .map(|&i| Side {
+                language: sides[i].0,
+                is_source: i == 0,
+                text: &sides[i].1,
+            })
+            .collect(),
+        notes: tu
+            .notes
+            .iter()
+            .map(|n| n.text.as_str())
+            .chain(
+                tu.memoq
+                    .iter()
+                    .flat_map(|m| &m.comments)
+                    .map(|c| c.text.as_str()),
+            )
+            .collect(),
+        state,
+    };
+
+    for (side, spans) in match_unit(matcher, &view) {
+        let i = in_scope[side];
+        let (language, text, position) = &sides[i];
+        let mut match_result = MatchResult::new(from.format, language, text.to_owned(), *position);
+        match_result.set_occurrences(spans);
+        match_result.similarity = matcher.similarity(text);
+
+        let (other_language, other, _) = &sides[1 - i];
+        match_result
+            .other_languages
+            .insert(other_language.to_string(), vec![other.to_owned()]);
+        match_results.push(match_result);
+    }
+}
+
 pub trait SearchInFile {
     fn search_in_file(
         &self,
-        include_tags: bool,
-        matcher: &Box<dyn SearchString>,
+        options: &SearchOptions,
+        matcher: &dyn SearchString,
     ) -> Vec<MatchResult>;
+
+    /// Returns the units whose source and target satisfy `query` together.
+    /// In TMX and TBX every other language is checked against the source;
+    /// `options.scope` can narrow that down.
+    fn search_bilingual(
+        &self,
+        options: &SearchOptions,
+        query: &BilingualQuery,
+    ) -> Vec<BilingualMatch>;
+
+    /// Counts the hits of `matcher` in the file, in total and per language.
+    fn count_in_file(&self, options: &SearchOptions, matcher: &dyn SearchString) -> HitCounts {
+        return HitCounts::from_results(&self.search_in_file(options, matcher));
+    }
 }
 
 pub trait IsTranslationXML: GetMeta + SearchInFile {
@@ -161,34 +628,25 @@ impl IsTranslationXML for xlsx::TranslationXlsx {
     }
 }
 
-pub fn read_file_with_parser(path: &PathBuf) -> Box<dyn IsTranslationXML> {
-    match path
-        .extension()
-        .unwrap_or_default()
-        .to_str()
-        .unwrap_or_default()
-    {
-        "xliff" | "xlf" | "txlf" | "sdlxliff" | "mxliff" | "mqxliff" => {
-            Box::new(xliff::XliffFile::new(path.to_str().unwrap_or_default()))
-        }
-        "xlz" => Box::new(xliff::XliffFile::new_zipped(
-            path.to_str().unwrap_or_default(),
-            "content.xlf",
-        )),
-        "mqxlz" => Box::new(xliff::XliffFile::new_zipped(
-            path.to_str().unwrap_or_default(),
-            "document.mqxliff",
-        )),
-        "tmx" => Box::new(tmx::TmxFile::new(path.to_str().unwrap_or_default())),
-        "tbx" => Box::new(tbx::TbxFile::new(path.to_str().unwrap_or_default())),
-        "xlsx" => Box::new(xlsx::TranslationXlsx::new(
-            path.to_str().unwrap_or_default(),
-        )),
-        _ => panic!("Unsupported file type"), // use a cli-compliant way to panic
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Parses `path` with the parser matching its detected format; see
+/// [`detect_format`]. The result reports the format through `get_format`.
+pub fn read_file_with_parser(path: &Path) -> Result<Box<dyn IsTranslationXML>> {
+    let path_str = path.to_str().unwrap_or_default();
+
+    let parsed: Box<dyn IsTranslationXML> = match detect_format(path)? {
+        FileFormat::Xliff => Box::new(xliff::XliffFile::new(path_str)?),
+        FileFormat::Xlz => Box::new(xliff::XliffFile::new_zipped(path_str, "content.xlf")?),
+        FileFormat::Mqxlz => Box::new(xliff::XliffFile::new_zipped(path_str, "document.mqxliff")?),
+        FileFormat::Sdlppx => Box::new(xliff::XliffFile::new_sdlppx(path_str)?),
+        FileFormat::Tmx => Box::new(tmx::TmxFile::new(path_str)?),
+        FileFormat::Tbx => Box::new(tbx::TbxFile::new(path_str)?),
+        FileFormat::Xlsx => Box::new(xlsx::TranslationXlsx::new(path_str)?),
+    };
+
+    return Ok(parsed);
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum SegNode {
     Text(String),
     OpenOrCloseNode {
@@ -202,13 +660,15 @@ pub enum SegNode {
     },
 }
 
-pub fn is_text_node(node: &&Box<SegNode>) -> bool {
-    let allowed_inline_text_tags = ["g", "mrk"];
+/// Whether the node holds translatable text, as opposed to native code
+/// (`bpt`, `ept`, `it`, `ph`, `ut`) or placeholders.
+pub fn is_text_node(node: &SegNode) -> bool {
+    let allowed_inline_text_tags = ["g", "mrk", "pc", "hi", "sub"];
 
-    let allow_tag = match node.as_ref() {
+    let allow_tag = match node {
         SegNode::Text(..) => true,
         SegNode::OpenOrCloseNode { node_type, .. } => {
-            allowed_inline_text_tags.contains(&node_type.as_str()) && true
+            allowed_inline_text_tags.contains(&node_type.as_str())
         }
         SegNode::SelfClosingNode { .. } => false,
     };
@@ -222,17 +682,9 @@ impl<'a> FromIterator<&'a Box<SegNode>> for String {
 
         for n in iter {
             match n.as_ref() {
-                SegNode::Text(content) => s.push_str(&Box::new(content)),
+                SegNode::Text(content) => s.push_str(content),
                 SegNode::OpenOrCloseNode { content, .. } => {
-                    let mut node_text = String::default();
-
-                    let content = content.iter().collect::<String>();
-                    match unescape(&content) {
-                        Ok(text) => node_text = text.to_string(),
-                        Err(e) => eprintln!("Error escaping text '{}': {:?}", &content, e),
-                    }
-
-                    s.push_str(&node_text)
+                    s.push_str(&content.iter().collect::<String>())
                 }
                 SegNode::SelfClosingNode { .. } => s.push_str(""),
             }
@@ -243,64 +695,79 @@ impl<'a> FromIterator<&'a Box<SegNode>> for String {
 }
 
 impl SegNode {
-    fn parse_inline(reader: &mut Reader<&[u8]>, buf: &mut Vec<u8>) -> Vec<Box<SegNode>> {
+    fn write_xml(&self, out: &mut String) {
+        match self {
+            SegNode::Text(text) => out.push_str(&partial_escape(text)),
+            SegNode::OpenOrCloseNode {
+                node_type,
+                attributes,
+                content,
+            } => {
+                out.push_str(&format!("<{}{}>", node_type, attributes_to_xml(attributes)));
+                for n in content {
+                    n.write_xml(out);
+                }
+                out.push_str(&format!("</{}>", node_type));
+            }
+            SegNode::SelfClosingNode {
+                node_type,
+                attributes,
+            } => out.push_str(&format!(
+                "<{}{}/>",
+                node_type,
+                attributes_to_xml(attributes)
+            )),
+        }
+    }
+
+    /// Parses inline content up to the end tag of the enclosing element, or
+    /// to the end of input. Every inline element is kept with its nesting, so
+    /// the TMX 1.4b, XLIFF 1.2 and XLIFF 2.x inline sets all round-trip and
+    /// no text is dropped.
+    // boxed to match the public `content` and segment fields
+    #[allow(clippy::vec_box)]
+    fn parse_inline<R: BufRead>(
+        reader: &mut Reader<R>,
+        buf: &mut Vec<u8>,
+    ) -> Result<Vec<Box<SegNode>>> {
         let mut nodes = Vec::new();
 
         loop {
             match reader.read_event_into(buf) {
-                Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-                Ok(Event::Start(e)) => match e.name().as_ref() {
-                    b"bpt" | b"ept" | b"ph" | b"g" | b"mrk" => {
-                        let node_type = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                        let attributes = crate::get_attributes(&reader, &e);
-                        let recurse_content =
-                            reader.read_text(e.name()).unwrap_or_default().into_owned();
-                        if recurse_content.contains(['<', '>']) {
-                            let mut recurse_reader = Reader::from_str(&recurse_content);
-                            nodes.push(Box::new(SegNode::OpenOrCloseNode {
-                                node_type,
-                                attributes,
-                                content: SegNode::parse_inline(&mut recurse_reader, buf),
-                            }))
-                        } else {
-                            nodes.push(Box::new(SegNode::OpenOrCloseNode {
-                                node_type,
-                                attributes,
-                                content: vec![Box::new(SegNode::Text(recurse_content))],
-                            }))
-                        }
-                    }
-                    _ => (),
-                },
-                Ok(Event::Empty(e)) => match e.name().as_ref() {
-                    b"bx" | b"ex" | b"x" => {
-                        let node_type = String::from_utf8_lossy(e.name().as_ref()).to_string();
-                        let attributes = crate::get_attributes(&reader, &e);
-                        nodes.push(Box::new(SegNode::SelfClosingNode {
-                            node_type,
-                            attributes,
-                        }));
-                    }
-                    _ => (),
-                },
+                Err(e) => return Err(Error::xml(reader.buffer_position(), e)),
+                Ok(Event::Start(e)) => {
+                    let node_type = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                    let attributes = crate::get_attributes(reader, &e)?;
+                    buf.clear();
+                    nodes.push(Box::new(SegNode::OpenOrCloseNode {
+                        node_type,
+                        attributes,
+                        content: SegNode::parse_inline(reader, buf)?,
+                    }));
+                }
+                Ok(Event::Empty(e)) => {
+                    let node_type = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                    let attributes = crate::get_attributes(reader, &e)?;
+                    nodes.push(Box::new(SegNode::SelfClosingNode {
+                        node_type,
+                        attributes,
+                    }));
+                }
                 Ok(Event::Text(e)) => {
-                    let mut node_text = String::default();
-
-                    match e.unescape() {
-                        Ok(text) => node_text = text.to_string(),
-                        Err(e) => eprintln!("Error escaping text '{}': {:?}", &node_text, e),
-                    }
+                    let node_text = e
+                        .unescape()
+                        .map_err(|err| Error::xml(reader.buffer_position(), err))?
+                        .to_string();
 
                     nodes.push(Box::new(SegNode::Text(node_text)))
                 }
-                Ok(Event::End(e)) => match e.name().as_ref() {
-                    // NOTE: you have to add end tag here to break
-                    b"source" | b"target" | b"seg" | b"term" => {
-                        return nodes;
-                    }
-                    _ => (),
-                },
-                Ok(Event::Eof) => return nodes,
+                Ok(Event::CData(e)) => {
+                    let node_text = String::from_utf8_lossy(&e.into_inner()).to_string();
+                    nodes.push(Box::new(SegNode::Text(node_text)))
+                }
+                // nested elements consume their own end tags, so this one
+                // closes the element whose content is being parsed
+                Ok(Event::End(..)) | Ok(Event::Eof) => return Ok(nodes),
                 _ => (),
             }
             buf.clear();
@@ -308,57 +775,140 @@ impl SegNode {
     }
 }
 
-pub fn read_to_string(path: &str) -> String {
-    let mut file = match File::open(&path) {
-        Ok(file) => file,
-        Err(e) => {
-            eprintln!("Failed to open {}: {:?}", path, e);
-            exit(1);
-        }
-    };
+pub fn read_to_string(path: &str) -> Result<String> {
+    let mut file = File::open(path).map_err(|e| Error::Io {
+        path: path.to_owned(),
+        source: e,
+    })?;
 
     let mut content = String::new();
 
-    match file.read_to_string(&mut content) {
-        Ok(..) => (),
-        Err(..) => {
-            eprintln!("Failed to read {} into a string", path);
-            exit(4);
+    file.read_to_string(&mut content).map_err(|e| Error::Io {
+        path: path.to_owned(),
+        source: e,
+    })?;
+
+    return Ok(content);
+}
+
+/// Concatenates the translatable text of `segs`. Native code is skipped
+/// unless `include_tags` is set, but `<sub>` text inside it is kept.
+pub fn extract_text(segs: &[Box<SegNode>], include_tags: bool) -> String {
+    let mut s = String::new();
+
+    for n in segs {
+        push_text(n, include_tags, &mut s);
+    }
+
+    return s;
+}
+
+fn push_text(node: &SegNode, include_tags: bool, out: &mut String) {
+    match node {
+        SegNode::Text(text) => out.push_str(text),
+        SegNode::OpenOrCloseNode { content, .. } => {
+            if include_tags || is_text_node(node) {
+                for n in content {
+                    push_text(n, include_tags, out);
+                }
+            } else {
+                for n in content {
+                    push_sub_text(n, out);
+                }
+            }
+        }
+        SegNode::SelfClosingNode { .. } => (),
+    }
+}
+
+/// Inside native code only the text of `<sub>` elements is translatable.
+fn push_sub_text(node: &SegNode, out: &mut String) {
+    if let SegNode::OpenOrCloseNode {
+        node_type, content, ..
+    } = node
+    {
+        if node_type == "sub" {
+            push_text(node, false, out);
+        } else {
+            for n in content {
+                push_sub_text(n, out);
+            }
         }
     }
+}
 
-    return content;
+/// Reads the unescaped text content of the element opened by `start`,
+/// consuming its end tag. Text of nested elements is included, their markup
+/// is not.
+pub(crate) fn read_element_text<R: BufRead>(
+    reader: &mut Reader<R>,
+    start: &BytesStart,
+) -> Result<String> {
+    let mut buf = Vec::new();
+    let mut text = String::new();
+    let mut depth = 0;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Err(e) => return Err(Error::xml(reader.buffer_position(), e)),
+            Ok(Event::Text(e)) => text.push_str(
+                &e.unescape()
+                    .map_err(|err| Error::xml(reader.buffer_position(), err))?,
+            ),
+            Ok(Event::CData(e)) => text.push_str(&String::from_utf8_lossy(&e.into_inner())),
+            Ok(Event::Start(..)) => depth += 1,
+            Ok(Event::End(..)) if depth == 0 => return Ok(text),
+            Ok(Event::End(..)) => depth -= 1,
+            Ok(Event::Eof) => {
+                let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+                return Err(Error::xml(
+                    reader.buffer_position(),
+                    quick_xml::Error::UnexpectedEof(name),
+                ));
+            }
+            _ => (),
+        }
+        buf.clear();
+    }
 }
 
-pub fn extract_text(segs: &Vec<Box<SegNode>>, include_tags: bool) -> String {
-    return segs
-        .iter()
-        .filter(|n| include_tags || is_text_node(n))
+/// Serializes inline content back into markup, escaping text and attribute
+/// values. Attributes are written in sorted order so output is stable.
+pub fn segs_to_xml(segs: &[Box<SegNode>]) -> String {
+    let mut s = String::new();
+
+    for n in segs {
+        n.write_xml(&mut s);
+    }
+
+    return s;
+}
+
+pub(crate) fn attributes_to_xml(attributes: &HashMap<String, String>) -> String {
+    let mut keys = attributes.keys().collect::<Vec<_>>();
+    keys.sort();
+
+    return keys
+        .into_iter()
+        .map(|k| format!(" {}=\"{}\"", k, escape(&attributes[k])))
         .collect::<String>();
 }
 
-pub fn get_attributes(reader: &Reader<&[u8]>, start: &BytesStart) -> HashMap<String, String> {
+pub fn get_attributes<R>(
+    reader: &Reader<R>,
+    start: &BytesStart,
+) -> Result<HashMap<String, String>> {
     let mut attributes = HashMap::new();
 
     for i in start.attributes() {
-        let attribute = match i.as_ref() {
-            Ok(attr) => attr,
-            Err(e) => {
-                eprintln!(
-                    "Failed to reference attribute at {} {:?}",
-                    reader.buffer_position(),
-                    e
-                );
-                exit(2);
-            }
-        };
+        let attribute = i.map_err(|e| Error::attribute(reader.buffer_position(), e))?;
         attributes.insert(
             String::from_utf8_lossy(attribute.key.into_inner()).into_owned(),
             attribute
                 .decode_and_unescape_value(reader)
-                .unwrap_or_default()
+                .map_err(|e| Error::xml(reader.buffer_position(), e))?
                 .into_owned(),
         );
     }
-    return attributes;
+    return Ok(attributes);
 }
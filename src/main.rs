@@ -0,0 +1,217 @@
+use clap::Parser;
+use fancy_regex::Regex;
+use std::process::ExitCode;
+use std::sync::Arc;
+use translation_xml_parser::batch::{search_paths, SearchEvent};
+use translation_xml_parser::query::Query;
+use translation_xml_parser::tmx::Prop;
+use translation_xml_parser::xliff::UnitStatus;
+use translation_xml_parser::{
+    Fuzziness, FuzzyMatcher, HitCounts, MatchOptions, Normalization, SearchOptions, SearchScope,
+    SearchString, TextMatcher,
+};
+
+/// Search translation files (XLIFF, TMX, TBX, XLSX) under directories and
+/// glob patterns.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// Text to look for, or a regular expression with --regex
+    pattern: String,
+    /// Files, directories (searched recursively) or glob patterns
+    #[arg(required = true)]
+    paths: Vec<String>,
+    /// Treat the pattern as a regular expression
+    #[arg(short, long)]
+    regex: bool,
+    /// Treat the pattern as a query, e.g. `src:account -tgt:账户`
+    #[arg(short, long, conflicts_with = "regex")]
+    query: bool,
+    /// Find substrings at most this many character edits from the pattern
+    #[arg(long, conflicts_with_all = ["regex", "query"])]
+    max_edits: Option<usize>,
+    /// Count swapping two adjacent characters as one edit with --max-edits
+    #[arg(long, requires = "max_edits")]
+    damerau: bool,
+    /// Find texts whose words are at least this many percent similar to the
+    /// pattern
+    #[arg(long, conflicts_with_all = ["regex", "query", "max_edits"], value_parser = clap::value_parser!(u8).range(1..=100))]
+    similarity: Option<u8>,
+    /// Ignore case; also applies to --regex
+    #[arg(short, long)]
+    ignore_case: bool,
+    /// Compare texts after Unicode normalization: nfc or nfkc
+    #[arg(long, conflicts_with = "regex", value_parser = parse_normalization)]
+    normalize: Option<Normalization>,
+    /// Ignore accents and other diacritics
+    #[arg(long, conflicts_with = "regex")]
+    strip_accents: bool,
+    /// Treat full-width and half-width forms alike
+    #[arg(long, conflicts_with = "regex")]
+    fold_width: bool,
+    /// Also match the native code inside inline tags
+    #[arg(long)]
+    include_tags: bool,
+    /// Also search XLIFF alt-trans candidates
+    #[arg(long)]
+    alt_trans: bool,
+    /// Only search units with this status
+    #[arg(long, value_parser = parse_status)]
+    status: Option<UnitStatus>,
+    /// Only search TMX units with this prop, given as TYPE=VALUE
+    #[arg(long, value_parser = parse_prop)]
+    prop: Vec<Prop>,
+    /// Only search TMX units changed at or after this date
+    #[arg(long)]
+    changed_after: Option<String>,
+    /// Only search TMX units changed before this date
+    #[arg(long)]
+    changed_before: Option<String>,
+    /// Only search the source side
+    #[arg(long, conflicts_with_all = ["target", "lang"])]
+    source: bool,
+    /// Only search the target side
+    #[arg(long, conflicts_with = "lang")]
+    target: bool,
+    /// Only search these languages, e.g. `zh-CN` or `de` for every German
+    #[arg(long)]
+    lang: Vec<String>,
+    /// Print one JSON object per match
+    #[arg(long)]
+    json: bool,
+    /// Only print the number of hits per file and language
+    #[arg(short, long)]
+    count: bool,
+}
+
+fn parse_status(value: &str) -> Result<UnitStatus, String> {
+    match value {
+        "translated" => Ok(UnitStatus::Translated),
+        "untranslated" => Ok(UnitStatus::Untranslated),
+        "locked" => Ok(UnitStatus::Locked),
+        _ => Err("expected translated, untranslated or locked".to_string()),
+    }
+}
+
+fn parse_normalization(value: &str) -> Result<Normalization, String> {
+    match value {
+        "nfc" => Ok(Normalization::Nfc),
+        "nfkc" => Ok(Normalization::Nfkc),
+        _ => Err("expected nfc or nfkc".to_string()),
+    }
+}
+
+fn parse_prop(value: &str) -> Result<Prop, String> {
+    let (prop_type, value) = value
+        .split_once('=')
+        .ok_or_else(|| "expected TYPE=VALUE".to_string())?;
+    return Ok(Prop {
+        prop_type: prop_type.to_string(),
+        value: value.to_string(),
+    });
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let match_options = MatchOptions {
+        ignore_case: cli.ignore_case,
+        normalization: cli.normalize,
+        strip_diacritics: cli.strip_accents,
+        fold_width: cli.fold_width,
+    };
+
+    let matcher: Arc<dyn SearchString> = if cli.regex {
+        let pattern = if cli.ignore_case {
+            format!("(?i){}", cli.pattern)
+        } else {
+            cli.pattern.to_owned()
+        };
+        match Regex::new(&pattern) {
+            Ok(regex) => Arc::new(regex),
+            Err(e) => {
+                eprintln!("Invalid regular expression: {}", e);
+                return ExitCode::from(2);
+            }
+        }
+    } else if cli.query {
+        match Query::parse_with(&cli.pattern, &match_options) {
+            Ok(query) => Arc::new(query),
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::from(2);
+            }
+        }
+    } else if let Some(max_edits) = cli.max_edits {
+        Arc::new(FuzzyMatcher::new(
+            &cli.pattern,
+            Fuzziness::Edits {
+                max_edits,
+                transpositions: cli.damerau,
+            },
+            match_options,
+        ))
+    } else if let Some(min_similarity) = cli.similarity {
+        Arc::new(FuzzyMatcher::new(
+            &cli.pattern,
+            Fuzziness::Similarity { min_similarity },
+            match_options,
+        ))
+    } else if match_options.is_exact() {
+        Arc::new(cli.pattern)
+    } else {
+        Arc::new(TextMatcher::new(&cli.pattern, match_options))
+    };
+
+    let scope = if cli.source {
+        SearchScope::Source
+    } else if cli.target {
+        SearchScope::Target
+    } else if !cli.lang.is_empty() {
+        SearchScope::Languages(cli.lang)
+    } else {
+        SearchScope::All
+    };
+
+    let options = SearchOptions {
+        include_tags: cli.include_tags,
+        include_alt_trans: cli.alt_trans,
+        status: cli.status,
+        props: cli.prop,
+        changed_after: cli.changed_after,
+        changed_before: cli.changed_before,
+        scope,
+    };
+
+    let mut failed = false;
+    let mut total = HitCounts::default();
+    for event in search_paths(&cli.paths, options, matcher) {
+        match event {
+            SearchEvent::Match(_) if cli.count => (),
+            SearchEvent::Match(result) if cli.json => match serde_json::to_string(&result) {
+                Ok(line) => println!("{}", line),
+                Err(e) => eprintln!("{}", e),
+            },
+            SearchEvent::Match(result) => print!("{}", result),
+            SearchEvent::Finished { path, counts } => {
+                if cli.count && counts.total != 0 {
+                    println!("{}: {}", path.display(), counts);
+                }
+                total.add(&counts);
+            }
+            SearchEvent::Failed { error, .. } => {
+                eprintln!("{}", error);
+                failed = true;
+            }
+        }
+    }
+
+    if cli.count {
+        println!("total: {}", total);
+    }
+
+    if failed {
+        return ExitCode::FAILURE;
+    }
+    return ExitCode::SUCCESS;
+}
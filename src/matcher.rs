@@ -0,0 +1,227 @@
+use crate::SearchString;
+use std::any::Any;
+use std::ops::Range;
+use unicode_normalization::char::canonical_combining_class;
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    Nfc,
+    Nfkc,
+}
+
+/// How loosely a [`TextMatcher`] compares texts. The default is exact.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchOptions {
+    /// Compare lowercased texts; Turkish `İ` and `ı` both fold to `i`.
+    pub ignore_case: bool,
+    pub normalization: Option<Normalization>,
+    /// Drop diacritical marks, so `résumé` matches `resume`. Marks that
+    /// change the letter in other scripts, like the Japanese dakuten, stay.
+    pub strip_diacritics: bool,
+    /// Fold full-width and half-width forms, e.g. `，` to `,`, `Ａ` to `A`
+    /// and `ｶ` to `カ`.
+    pub fold_width: bool,
+}
+
+/// The combining diacritical mark blocks.
+fn is_diacritic(c: char) -> bool {
+    return matches!(
+        c,
+        '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE20}'..='\u{FE2F}'
+    );
+}
+
+impl MatchOptions {
+    pub fn is_exact(&self) -> bool {
+        return *self == MatchOptions::default();
+    }
+
+    pub fn fold(&self, text: &str) -> String {
+        return FoldedText::new(text, self).folded;
+    }
+
+    /// Folds one combining sequence: a starter and the marks after it.
+    fn fold_sequence(&self, sequence: &str) -> String {
+        let mut folded = if self.fold_width {
+            sequence
+                .chars()
+                .flat_map(|c| {
+                    let mut folded = String::new();
+                    if c == '\u{3000}' || ('\u{FF00}'..='\u{FFEF}').contains(&c) {
+                        folded.extend(c.to_string().nfkc());
+                    } else {
+                        folded.push(c);
+                    }
+                    folded.chars().collect::<Vec<_>>()
+                })
+                .collect::<String>()
+        } else {
+            sequence.to_owned()
+        };
+
+        if self.strip_diacritics {
+            // composing again keeps Hangul syllables whole
+            folded = folded.nfd().filter(|c| !is_diacritic(*c)).nfc().collect();
+        }
+
+        folded = match self.normalization {
+            Some(Normalization::Nfc) => folded.nfc().collect(),
+            Some(Normalization::Nfkc) => folded.nfkc().collect(),
+            None => folded,
+        };
+
+        if self.ignore_case {
+            folded = folded
+                .chars()
+                .flat_map(|c| match c {
+                    'İ' | 'ı' => vec!['i'],
+                    _ => c.to_lowercase().collect(),
+                })
+                .collect();
+        }
+
+        return folded;
+    }
+}
+
+/// A text folded by [`MatchOptions`], mapping back to the original.
+pub(crate) struct FoldedText {
+    pub(crate) folded: String,
+    /// For each byte of `folded`, the range of the original it comes from.
+    origins: Vec<Range<usize>>,
+}
+
+impl FoldedText {
+    /// Combining sequences are folded one at a time, so a match starting or
+    /// ending inside one maps back to the whole sequence.
+    pub(crate) fn new(text: &str, options: &MatchOptions) -> FoldedText {
+        let mut folded = String::with_capacity(text.len());
+        let mut origins = Vec::with_capacity(text.len());
+
+        let mut boundaries = text
+            .char_indices()
+            .filter(|(i, c)| *i != 0 && canonical_combining_class(*c) == 0)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        boundaries.push(text.len());
+
+        let mut start = 0;
+        for end in boundaries {
+            let sequence = options.fold_sequence(&text[start..end]);
+            folded.push_str(&sequence);
+            origins.extend(std::iter::repeat_n(start..end, sequence.len()));
+            start = end;
+        }
+
+        return FoldedText { folded, origins };
+    }
+
+    /// Maps non-overlapping spans of `folded` back to the original text.
+    pub(crate) fn original_spans(
+        &self,
+        folded_spans: impl IntoIterator<Item = Range<usize>>,
+    ) -> Vec<Range<usize>> {
+        let mut spans: Vec<Range<usize>> = Vec::new();
+        for folded_span in folded_spans {
+            let span = self.origins[folded_span.start].start..self.origins[folded_span.end - 1].end;
+            // two matches inside one combining sequence map to the same span
+            if spans.last().is_none_or(|last| span.start >= last.end) {
+                spans.push(span);
+            }
+        }
+
+        return spans;
+    }
+}
+
+/// A plain-text matcher that compares texts folded by its [`MatchOptions`].
+/// Reported spans point into the original, unfolded text.
+#[derive(Debug, Clone)]
+pub struct TextMatcher {
+    pattern: String,
+    options: MatchOptions,
+}
+
+impl TextMatcher {
+    pub fn new(pattern: &str, options: MatchOptions) -> TextMatcher {
+        return TextMatcher {
+            pattern: options.fold(pattern),
+            options,
+        };
+    }
+}
+
+impl SearchString for TextMatcher {
+    fn find_matches(&self, text: &str) -> Vec<Range<usize>> {
+        if self.pattern.is_empty() {
+            return Vec::new();
+        }
+
+        let folded = FoldedText::new(text, &self.options);
+        return folded.original_spans(
+            folded
+                .folded
+                .match_indices(&self.pattern)
+                .map(|(start, matched)| start..start + matched.len()),
+        );
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MatchOptions, Normalization, TextMatcher};
+    use crate::SearchString;
+
+    #[test]
+    fn folds_texts_and_maps_spans_back() {
+        let nfc = MatchOptions {
+            normalization: Some(Normalization::Nfc),
+            ..Default::default()
+        };
+        // decomposed "café"
+        let text = "un cafe\u{301} noir";
+        assert_eq!(TextMatcher::new("café", nfc).find_matches(text), vec![3..9]);
+        assert!("café".to_string().find_matches(text).is_empty());
+
+        let ignore_case = MatchOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let matcher = TextMatcher::new("istanbul", ignore_case);
+        assert_eq!(
+            matcher.find_matches("İSTANBUL, Istanbul"),
+            vec![0..9, 11..19]
+        );
+
+        let strip_diacritics = MatchOptions {
+            strip_diacritics: true,
+            ..Default::default()
+        };
+        let matcher = TextMatcher::new("Resume", strip_diacritics);
+        assert_eq!(matcher.find_matches("Résumé"), vec![0..8]);
+        // the dakuten is part of the kana: が stays が
+        assert!(TextMatcher::new("か", strip_diacritics)
+            .find_matches("が")
+            .is_empty());
+        assert_eq!(
+            TextMatcher::new("が", strip_diacritics).find_matches("が"),
+            vec![0..3]
+        );
+
+        let fold_width = MatchOptions {
+            fold_width: true,
+            ..Default::default()
+        };
+        let matcher = TextMatcher::new("ABC, カナ", fold_width);
+        assert_eq!(matcher.find_matches("ＡＢＣ，　ｶﾅ"), vec![0..21]);
+    }
+}
@@ -0,0 +1,504 @@
+use crate::matcher::{MatchOptions, TextMatcher};
+use crate::{language_matches, Error, Result, SearchString, Side, UnitView};
+use fancy_regex::Regex;
+use std::any::Any;
+use std::ops::Range;
+
+/// What a term of a [`Query`] is matched against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    /// Any text of the unit.
+    Text,
+    Source,
+    Target,
+    /// The texts in a BCP-47 language range, see [`crate::SearchScope`].
+    Language(String),
+    Id,
+    Note,
+    State,
+}
+
+/// A boolean search query, compiled from a string by [`Query::parse`]:
+///
+/// - `account password` or `account AND password`: both terms match
+/// - `account OR 账户`, `NOT draft` or `-draft`, with `( )` for grouping
+/// - `"new account"`: a phrase; `/acc(ou)?nt/`: a regular expression
+/// - `src:`, `tgt:`, `lang:de:`, `id:`, `note:` and `state:` prefixes
+///   restrict a term to one field of the unit
+///
+/// Terms are exact substrings unless parsed with [`Query::parse_with`].
+/// `NOT` binds tightest, then `AND`, then `OR`.
+pub enum Query {
+    Term {
+        field: Field,
+        matcher: Box<dyn SearchString>,
+    },
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Query> {
+        return Query::parse_with(query, &MatchOptions::default());
+    }
+
+    /// Parses a query whose text terms match with `options`; regular
+    /// expressions are not affected.
+    pub fn parse_with(query: &str, options: &MatchOptions) -> Result<Query> {
+        let mut parser = Parser {
+            query,
+            options,
+            tokens: tokenize(query)?,
+            next: 0,
+        };
+
+        let parsed = parser.parse_or()?;
+        if let Some((token, offset)) = parser.tokens.get(parser.next) {
+            let reason = match token {
+                Token::Close => "unmatched )".to_string(),
+                _ => format!("unexpected {:?}", token),
+            };
+            return Err(invalid(query, *offset, reason));
+        }
+
+        return Ok(parsed);
+    }
+
+    /// Evaluates the query on a whole unit.
+    fn holds(&self, unit: &UnitView) -> bool {
+        match self {
+            Query::Term { field, matcher } => {
+                let found = |text: &str| !matcher.find_matches(text).is_empty();
+                match field {
+                    Field::Id => found(unit.id),
+                    Field::Note => unit.notes.iter().any(|n| found(n)),
+                    Field::State => unit.state.is_some_and(found),
+                    _ => unit
+                        .sides
+                        .iter()
+                        .any(|side| field.applies_to(side) && found(side.text)),
+                }
+            }
+            Query::And(queries) => queries.iter().all(|q| q.holds(unit)),
+            Query::Or(queries) => queries.iter().any(|q| q.holds(unit)),
+            Query::Not(query) => !query.holds(unit),
+        }
+    }
+
+    fn holds_for_text(&self, text: &str) -> bool {
+        match self {
+            Query::Term { field, matcher } => {
+                field.is_text() && !matcher.find_matches(text).is_empty()
+            }
+            Query::And(queries) => queries.iter().all(|q| q.holds_for_text(text)),
+            Query::Or(queries) => queries.iter().any(|q| q.holds_for_text(text)),
+            Query::Not(query) => !query.holds_for_text(text),
+        }
+    }
+
+    /// Collects the matches in `side` of every term that is not negated.
+    /// A `lone` side takes the terms of every text field.
+    fn collect_spans(&self, side: &Side, lone: bool, spans: &mut Vec<Range<usize>>) {
+        match self {
+            Query::Term { field, matcher } => {
+                let applies = if lone {
+                    field.is_text()
+                } else {
+                    field.applies_to(side)
+                };
+                if applies {
+                    spans.extend(matcher.find_matches(side.text));
+                }
+            }
+            Query::And(queries) | Query::Or(queries) => {
+                for query in queries {
+                    query.collect_spans(side, lone, spans);
+                }
+            }
+            Query::Not(_) => (),
+        }
+    }
+}
+
+impl Field {
+    /// Whether the field is a text of the unit rather than metadata.
+    fn is_text(&self) -> bool {
+        return !matches!(self, Field::Id | Field::Note | Field::State);
+    }
+
+    fn applies_to(&self, side: &Side) -> bool {
+        match self {
+            Field::Text => true,
+            Field::Source => side.is_source,
+            Field::Target => !side.is_source,
+            Field::Language(range) => language_matches(range, side.language),
+            Field::Id | Field::Note | Field::State => false,
+        }
+    }
+}
+
+/// Sorts `spans` and drops those overlapping an earlier one.
+fn merge_spans(mut spans: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    spans.sort_by_key(|span| (span.start, std::cmp::Reverse(span.end)));
+
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(spans.len());
+    for span in spans {
+        if merged.last().is_none_or(|last| span.start >= last.end) {
+            merged.push(span);
+        }
+    }
+
+    return merged;
+}
+
+impl SearchString for Query {
+    /// Matches a lone text, which stands in for every side of a unit: only
+    /// `id:`, `note:` and `state:` terms never match it.
+    fn find_matches(&self, text: &str) -> Vec<Range<usize>> {
+        if !self.holds_for_text(text) {
+            return Vec::new();
+        }
+
+        let side = Side {
+            language: "",
+            is_source: true,
+            text,
+        };
+        let mut spans = Vec::new();
+        self.collect_spans(&side, true, &mut spans);
+        return merge_spans(spans);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn accepts_unit(&self, unit: &UnitView) -> bool {
+        return self.holds(unit);
+    }
+
+    fn find_matches_in(&self, side: &Side) -> Vec<Range<usize>> {
+        let mut spans = Vec::new();
+        self.collect_spans(side, false, &mut spans);
+        return merge_spans(spans);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Term { field: Field, value: Value },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Text(String),
+    Regex(String),
+}
+
+fn invalid(query: &str, offset: usize, reason: String) -> Error {
+    return Error::InvalidQuery {
+        query: query.to_owned(),
+        offset,
+        reason,
+    };
+}
+
+/// Splits `query` into tokens with their byte offsets.
+fn tokenize(query: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = query.char_indices().peekable();
+
+    while let Some(&(offset, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let token = match c {
+            '(' => {
+                chars.next();
+                Token::Open
+            }
+            ')' => {
+                chars.next();
+                Token::Close
+            }
+            '-' => {
+                chars.next();
+                Token::Not
+            }
+            _ => {
+                let field = read_field(query, offset, &mut chars)?;
+                let value = read_value(query, &mut chars)?;
+                // a quoted "AND" is a phrase, not an operator
+                let bare = field == Field::Text && c != '"';
+                match &value {
+                    Value::Text(word) if bare && word == "AND" => Token::And,
+                    Value::Text(word) if bare && word == "OR" => Token::Or,
+                    Value::Text(word) if bare && word == "NOT" => Token::Not,
+                    _ => Token::Term { field, value },
+                }
+            }
+        };
+        tokens.push((token, offset));
+    }
+
+    return Ok(tokens);
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::CharIndices<'a>>;
+
+/// Reads a `src:`, `tgt:`, `lang:RANGE:`, `id:`, `note:` or `state:` prefix,
+/// if there is one.
+fn read_field(query: &str, offset: usize, chars: &mut Chars) -> Result<Field> {
+    let rest = &query[offset..];
+    let prefixes = [
+        ("src:", Field::Source),
+        ("tgt:", Field::Target),
+        ("id:", Field::Id),
+        ("note:", Field::Note),
+        ("state:", Field::State),
+    ];
+
+    let (len, field) = if let Some((prefix, field)) = prefixes
+        .into_iter()
+        .find(|(prefix, _)| rest.starts_with(prefix))
+    {
+        (prefix.len(), field)
+    } else if let Some(language) = rest.strip_prefix("lang:") {
+        let end = language
+            .find(|c: char| c == ':' || c.is_whitespace())
+            .filter(|end| language[*end..].starts_with(':'))
+            .ok_or_else(|| invalid(query, offset, "expected lang:RANGE:".to_string()))?;
+        (
+            "lang:".len() + end + 1,
+            Field::Language(language[..end].to_owned()),
+        )
+    } else {
+        return Ok(Field::Text);
+    };
+
+    while chars.peek().is_some_and(|(i, _)| *i < offset + len) {
+        chars.next();
+    }
+
+    return Ok(field);
+}
+
+/// Reads a `"phrase"`, a `/regex/` or a bare word. Inside quotes and
+/// slashes a backslash escapes the delimiter.
+fn read_value(query: &str, chars: &mut Chars) -> Result<Value> {
+    let (offset, first) = match chars.peek() {
+        Some(&(offset, c)) if !c.is_whitespace() && c != '(' && c != ')' => (offset, c),
+        Some(&(offset, _)) => return Err(invalid(query, offset, "expected a term".to_string())),
+        None => {
+            return Err(invalid(query, query.len(), "expected a term".to_string()));
+        }
+    };
+
+    if first == '"' || first == '/' {
+        chars.next();
+        let mut value = String::new();
+        loop {
+            match chars.next() {
+                Some((_, '\\')) if chars.peek().is_some_and(|(_, c)| *c == first) => {
+                    value.push(first);
+                    chars.next();
+                }
+                Some((_, c)) if c == first => break,
+                Some((_, c)) => value.push(c),
+                None => {
+                    return Err(invalid(query, offset, format!("missing closing {}", first)));
+                }
+            }
+        }
+
+        if first == '"' {
+            return Ok(Value::Text(value));
+        }
+        return Ok(Value::Regex(value));
+    }
+
+    let mut word = String::new();
+    while let Some(&(_, c)) = chars.peek() {
+        if c.is_whitespace() || c == '(' || c == ')' {
+            break;
+        }
+        word.push(c);
+        chars.next();
+    }
+
+    return Ok(Value::Text(word));
+}
+
+struct Parser<'a> {
+    query: &'a str,
+    options: &'a MatchOptions,
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        return self.tokens.get(self.next).map(|(token, _)| token);
+    }
+
+    fn offset(&self) -> usize {
+        return self
+            .tokens
+            .get(self.next)
+            .map(|(_, offset)| *offset)
+            .unwrap_or(self.query.len());
+    }
+
+    fn parse_or(&mut self) -> Result<Query> {
+        let mut queries = vec![self.parse_and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.next += 1;
+            queries.push(self.parse_and()?);
+        }
+
+        if queries.len() == 1 {
+            return Ok(queries.remove(0));
+        }
+        return Ok(Query::Or(queries));
+    }
+
+    fn parse_and(&mut self) -> Result<Query> {
+        let mut queries = vec![self.parse_not()?];
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.next += 1,
+                // juxtaposed terms are ANDed
+                Some(Token::Open | Token::Not | Token::Term { .. }) => (),
+                _ => break,
+            }
+            queries.push(self.parse_not()?);
+        }
+
+        if queries.len() == 1 {
+            return Ok(queries.remove(0));
+        }
+        return Ok(Query::And(queries));
+    }
+
+    fn parse_not(&mut self) -> Result<Query> {
+        if self.peek() == Some(&Token::Not) {
+            self.next += 1;
+            return Ok(Query::Not(Box::new(self.parse_not()?)));
+        }
+        return self.parse_primary();
+    }
+
+    fn parse_primary(&mut self) -> Result<Query> {
+        let offset = self.offset();
+        let token = match self.tokens.get(self.next) {
+            Some((token, _)) => token.to_owned(),
+            None => {
+                return Err(invalid(self.query, offset, "expected a term".to_string()));
+            }
+        };
+        self.next += 1;
+
+        match token {
+            Token::Open => {
+                let query = self.parse_or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(invalid(self.query, offset, "unmatched (".to_string()));
+                }
+                self.next += 1;
+                return Ok(query);
+            }
+            Token::Term { field, value } => {
+                let matcher: Box<dyn SearchString> = match value {
+                    Value::Text(text) if self.options.is_exact() => Box::new(text),
+                    Value::Text(text) => Box::new(TextMatcher::new(&text, *self.options)),
+                    Value::Regex(pattern) => Box::new(
+                        Regex::new(&pattern)
+                            .map_err(|e| invalid(self.query, offset, e.to_string()))?,
+                    ),
+                };
+                return Ok(Query::Term { field, matcher });
+            }
+            other => {
+                return Err(invalid(
+                    self.query,
+                    offset,
+                    format!("unexpected {:?}", other),
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Query;
+    use crate::{MatchOptions, SearchInFile, SearchOptions, SearchString};
+
+    #[test]
+    fn combines_terms_and_fields() {
+        let t = crate::xliff::XliffFile::new("./tests/approval.sdlxliff").unwrap();
+        let options = SearchOptions::default();
+        let ids = |query: &str| {
+            let query = Query::parse(query).unwrap();
+            let mut ids = t
+                .search_in_file(&options, &query)
+                .into_iter()
+                .map(|m| m.unit_id)
+                .collect::<Vec<_>>();
+            ids.dedup();
+            ids
+        };
+
+        assert_eq!(ids("src:approv tgt:genehmig"), vec!["a1b2c3"]);
+        assert_eq!(ids("src:approv -tgt:genehmig"), vec!["d4e5f6"]);
+        assert_eq!(ids("ready OR \"shown here\""), vec!["a1b2c3", "d4e5f6"]);
+        assert_eq!(
+            ids("lang:de:/Genehmigungs\\w+/ AND note:term"),
+            vec!["d4e5f6"]
+        );
+        assert_eq!(ids("state:Draft"), vec!["d4e5f6"]);
+        assert_eq!(ids("id:g7h8i9"), vec!["g7h8i9"]);
+
+        let query = Query::parse("src:approv tgt:genehmig").unwrap();
+        let found = t.search_in_file(&options, &query);
+        let counts = t.count_in_file(&options, &query);
+        let hits = found.iter().map(|m| m.occurrences.len()).sum::<usize>();
+        assert_ne!(hits, 0);
+        assert_eq!(counts.total, hits);
+        assert_eq!(counts.languages["de-de"], 1);
+    }
+
+    #[test]
+    fn matches_lone_texts_and_reports_errors() {
+        let query = Query::parse("(account OR 账户) NOT password").unwrap();
+        assert_eq!(
+            query.find_matches("a new account, an account"),
+            vec![6..13, 18..25]
+        );
+        assert!(query.find_matches("account password").is_empty());
+
+        let options = MatchOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let query = Query::parse_with("src:Account -/Password/", &options).unwrap();
+        assert_eq!(query.find_matches("ACCOUNT password"), vec![0..7]);
+        assert!(query.find_matches("ACCOUNT Password").is_empty());
+
+        for broken in ["(account", "account )", "src:", "\"open", "lang:de", "/(/"] {
+            assert!(
+                matches!(Query::parse(broken), Err(crate::Error::InvalidQuery { .. })),
+                "{}",
+                broken
+            );
+        }
+    }
+}
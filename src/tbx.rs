@@ -1,95 +1,291 @@
-use crate::{extract_text, GetMeta, MatchResult, MetaInfo, SearchInFile, SearchString, SegNode};
+use crate::{
+    extract_text, match_unit, BilingualMatch, BilingualQuery, Error, FileFormat, GetMeta,
+    MatchResult, MetaInfo, Position, Result, SearchInFile, SearchOptions, SearchString, SegNode,
+    Side, UnitView,
+};
 use quick_xml::events::Event;
 use quick_xml::reader::Reader;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+mod writer;
+
+pub use writer::TbxDialect;
+
 pub struct TbxFile {
     pub path: String,
     pub term_entries: Vec<TermEntry>,
-    raw_content: String,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct TermEntry {
-    #[serde(rename = "$value")]
+    pub id: String,
+    pub descrips: Vec<Descrip>,
+    pub notes: Vec<String>,
+    pub xrefs: Vec<Xref>,
     pub lang_sets: Vec<LangSet>,
 }
 
+/// A descriptive field such as `definition`, `context` or `subjectField`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Descrip {
+    pub descrip_type: String,
+    pub value: String,
+}
+
+/// A term-level field such as `partOfSpeech`, `administrativeStatus` or
+/// `grammaticalGender`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TermNote {
+    pub note_type: String,
+    pub value: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Xref {
+    pub xref_type: String,
+    pub target: String,
+    pub value: String,
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct LangSet {
-    #[serde(rename = "$primitive=xml:lang")]
     pub language: String,
-    #[serde(rename = "$value")]
-    pub tig: Tig,
+    pub descrips: Vec<Descrip>,
+    pub notes: Vec<String>,
+    pub tigs: Vec<Tig>,
 }
 
+/// One term with its metadata, from a `tig`, `ntig` or TBX v3 `termSec`.
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Tig {
-    #[serde(rename = "$value")]
+    pub id: String,
     pub term: Vec<Box<SegNode>>,
+    pub term_notes: Vec<TermNote>,
+    pub descrips: Vec<Descrip>,
+    pub notes: Vec<String>,
+    pub xrefs: Vec<Xref>,
+    /// Where the `<term>` content starts in the document.
+    pub position: Position,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdministrativeStatus {
+    Preferred,
+    Admitted,
+    Deprecated,
+    Superseded,
+    Other(String),
+}
+
+impl Tig {
+    pub fn term_note(&self, note_type: &str) -> Option<&str> {
+        return self
+            .term_notes
+            .iter()
+            .find(|n| n.note_type == note_type)
+            .map(|n| n.value.as_str());
+    }
+
+    pub fn part_of_speech(&self) -> Option<&str> {
+        return self.term_note("partOfSpeech");
+    }
+
+    pub fn grammatical_gender(&self) -> Option<&str> {
+        return self.term_note("grammaticalGender");
+    }
+
+    /// Reads `administrativeStatus`, or the older `normativeAuthorization`,
+    /// accepting values with and without the `-admn-sts` suffix.
+    pub fn administrative_status(&self) -> Option<AdministrativeStatus> {
+        let value = self
+            .term_note("administrativeStatus")
+            .or_else(|| self.term_note("normativeAuthorization"))?;
+
+        let status = match value.trim_end_matches("-admn-sts") {
+            "preferredTerm" => AdministrativeStatus::Preferred,
+            "admittedTerm" => AdministrativeStatus::Admitted,
+            "deprecatedTerm" => AdministrativeStatus::Deprecated,
+            "supersededTerm" => AdministrativeStatus::Superseded,
+            _ => AdministrativeStatus::Other(value.to_owned()),
+        };
+
+        return Some(status);
+    }
+
+    pub fn is_preferred(&self) -> bool {
+        return self.administrative_status() == Some(AdministrativeStatus::Preferred);
+    }
+
+    /// Deprecated and superseded terms must not be used in translations.
+    pub fn is_forbidden(&self) -> bool {
+        return matches!(
+            self.administrative_status(),
+            Some(AdministrativeStatus::Deprecated) | Some(AdministrativeStatus::Superseded)
+        );
+    }
+}
+
+#[derive(PartialEq)]
+enum Level {
+    Entry,
+    LangSet,
+    Tig,
 }
 
 impl TbxFile {
-    pub fn new(path: &str) -> TbxFile {
-        let raw_content = crate::read_to_string(path);
+    pub fn new(path: &str) -> Result<TbxFile> {
+        let content = crate::read_to_string(path)?;
 
         let mut tbx_file = TbxFile {
             path: path.to_string(),
             term_entries: Vec::new(),
-            raw_content,
         };
-        tbx_file.parse();
-        return tbx_file;
+        tbx_file
+            .parse(&content)
+            .map_err(|e| e.locate(&content, path))?;
+        return Ok(tbx_file);
     }
 
-    fn parse(&mut self) {
+    /// Builds a termbase in memory, e.g. to export an edited glossary.
+    pub fn from_entries(term_entries: Vec<TermEntry>) -> TbxFile {
+        return TbxFile {
+            path: String::new(),
+            term_entries,
+        };
+    }
+
+    fn parse(&mut self, content: &str) -> Result<()> {
         let mut buf = Vec::new();
+        let mut level = Level::Entry;
 
         let mut cur_term_entry = TermEntry::default();
         let mut cur_lang_set = LangSet::default();
         let mut cur_tig = Tig::default();
 
-        let mut reader = Reader::from_str(&self.raw_content);
+        let mut reader = Reader::from_str(content);
+        let mut position = Position::start();
 
         loop {
             match reader.read_event_into(&mut buf) {
-                Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
+                Err(e) => return Err(Error::xml(reader.buffer_position(), e)),
                 Ok(Event::Start(e)) => match e.name().as_ref() {
-                    b"langSet" => {
-                        cur_lang_set.language = crate::get_attributes(&reader, &e)
+                    b"termEntry" | b"conceptEntry" => {
+                        cur_term_entry.id = crate::get_attributes(&reader, &e)?
+                            .get("id")
+                            .unwrap_or(&"".to_string())
+                            .to_owned();
+                    }
+                    b"langSet" | b"langSec" => {
+                        level = Level::LangSet;
+                        cur_lang_set.language = crate::get_attributes(&reader, &e)?
                             .get("xml:lang")
                             .unwrap_or(&"".to_string())
                             .to_owned()
                             .to_lowercase();
                     }
+                    b"tig" | b"ntig" | b"termSec" => {
+                        level = Level::Tig;
+                        cur_tig.id = crate::get_attributes(&reader, &e)?
+                            .get("id")
+                            .unwrap_or(&"".to_string())
+                            .to_owned();
+                    }
                     b"term" => {
-                        cur_tig = Tig {
-                            term: SegNode::parse_inline(&mut reader, &mut buf),
+                        position.advance_to(content, reader.buffer_position());
+                        cur_tig.position = position;
+                        cur_tig.term = SegNode::parse_inline(&mut reader, &mut buf)?;
+                    }
+                    b"termNote" => {
+                        let note_type = crate::get_attributes(&reader, &e)?
+                            .get("type")
+                            .unwrap_or(&"".to_string())
+                            .to_owned();
+                        cur_tig.term_notes.push(TermNote {
+                            note_type,
+                            value: crate::read_element_text(&mut reader, &e)?,
+                        });
+                    }
+                    b"descrip" => {
+                        let descrip_type = crate::get_attributes(&reader, &e)?
+                            .get("type")
+                            .unwrap_or(&"".to_string())
+                            .to_owned();
+                        let descrip = Descrip {
+                            descrip_type,
+                            value: crate::read_element_text(&mut reader, &e)?,
                         };
+                        match level {
+                            Level::Entry => cur_term_entry.descrips.push(descrip),
+                            Level::LangSet => cur_lang_set.descrips.push(descrip),
+                            Level::Tig => cur_tig.descrips.push(descrip),
+                        }
+                    }
+                    b"note" => {
+                        let note = crate::read_element_text(&mut reader, &e)?;
+                        match level {
+                            Level::Entry => cur_term_entry.notes.push(note),
+                            Level::LangSet => cur_lang_set.notes.push(note),
+                            Level::Tig => cur_tig.notes.push(note),
+                        }
+                    }
+                    b"xref" => {
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        let xref = Xref {
+                            xref_type: attributes.get("type").unwrap_or(&"".to_string()).to_owned(),
+                            target: attributes
+                                .get("target")
+                                .unwrap_or(&"".to_string())
+                                .to_owned(),
+                            value: crate::read_element_text(&mut reader, &e)?,
+                        };
+                        match level {
+                            Level::Tig => cur_tig.xrefs.push(xref),
+                            _ => cur_term_entry.xrefs.push(xref),
+                        }
                     }
                     _ => (),
                 },
+                Ok(Event::Empty(e)) if e.name().as_ref() == b"xref" => {
+                    let attributes = crate::get_attributes(&reader, &e)?;
+                    let xref = Xref {
+                        xref_type: attributes.get("type").unwrap_or(&"".to_string()).to_owned(),
+                        target: attributes
+                            .get("target")
+                            .unwrap_or(&"".to_string())
+                            .to_owned(),
+                        value: String::new(),
+                    };
+                    match level {
+                        Level::Tig => cur_tig.xrefs.push(xref),
+                        _ => cur_term_entry.xrefs.push(xref),
+                    }
+                }
                 Ok(Event::End(e)) => match e.name().as_ref() {
-                    b"termEntry" => {
-                        if cur_term_entry.lang_sets.len() != 0 {
+                    b"termEntry" | b"conceptEntry" => {
+                        if !cur_term_entry.lang_sets.is_empty() {
                             self.term_entries.push(cur_term_entry);
                         }
                         cur_term_entry = TermEntry::default();
                     }
-                    b"term" => {
-                        if cur_tig.term.len() != 0 {
-                            cur_lang_set.tig = cur_tig;
+                    b"tig" | b"ntig" | b"termSec" => {
+                        if !cur_tig.term.is_empty() {
+                            cur_lang_set.tigs.push(cur_tig);
                         }
                         cur_tig = Tig::default();
+                        level = Level::LangSet;
                     }
-                    b"langSet" => {
-                        if cur_tig.term.len() != 0 {
-                            cur_lang_set.tig = cur_tig;
+                    b"langSet" | b"langSec" => {
+                        // a bare <term> directly inside the langSet
+                        if !cur_tig.term.is_empty() {
+                            cur_lang_set.tigs.push(cur_tig);
+                        }
+                        if !cur_lang_set.tigs.is_empty() {
                             cur_term_entry.lang_sets.push(cur_lang_set);
                         }
                         cur_tig = Tig::default();
                         cur_lang_set = LangSet::default();
+                        level = Level::Entry;
                     }
                     _ => (),
                 },
@@ -98,18 +294,19 @@ impl TbxFile {
             }
             buf.clear()
         }
+
+        return Ok(());
     }
 }
 
 impl GetMeta for TbxFile {
-    fn get_meta(&self) -> MetaInfo {
+    fn get_meta(&self) -> MetaInfo<'_> {
         let mut languages = HashMap::new();
 
         for ls in self
             .term_entries
             .iter()
-            .map(|te| &te.lang_sets)
-            .flatten()
+            .flat_map(|te| &te.lang_sets)
             .collect::<Vec<_>>()
         {
             let cur_lang = ls.language.as_str();
@@ -117,48 +314,173 @@ impl GetMeta for TbxFile {
             languages.insert(cur_lang, acc_len + 1);
         }
 
-        return MetaInfo { languages };
+        return MetaInfo {
+            languages,
+            status: None,
+        };
     }
 
     fn get_filename(&self) -> String {
         return self.path.to_owned();
     }
+
+    fn get_format(&self) -> FileFormat {
+        return FileFormat::Tbx;
+    }
 }
 
 impl SearchInFile for TbxFile {
     fn search_in_file(
         &self,
-        include_tags: bool,
-        matcher: &Box<dyn SearchString>,
+        options: &SearchOptions,
+        matcher: &dyn SearchString,
     ) -> Vec<MatchResult> {
         let mut match_results = Vec::new();
 
-        for te in &self.term_entries {
-            for ls in &te.lang_sets {
-                let cur_term = extract_text(&ls.tig.term, include_tags);
-                if let Some(match_result) = matcher.match_string(&cur_term) {
-                    match_results.push(MatchResult {
-                        text: cur_term,
-                        matched: match_result,
-                        extra: te
-                            .lang_sets
-                            .iter()
-                            .filter(|l| l.language != ls.language)
-                            .map(|u| u.tig.term.iter().collect::<String>())
-                            .collect::<Vec<String>>(),
+        for (unit_index, te) in self.term_entries.iter().enumerate() {
+            // every term is one side; the first language set is the source
+            let terms = te
+                .lang_sets
+                .iter()
+                .enumerate()
+                .filter(|(i, ls)| options.scope.includes(&ls.language, *i == 0))
+                .flat_map(|(i, ls)| ls.tigs.iter().map(move |tig| (i == 0, ls, tig)))
+                .collect::<Vec<_>>();
+            let texts = terms
+                .iter()
+                .map(|(_, _, tig)| extract_text(&tig.term, options.include_tags))
+                .collect::<Vec<_>>();
+            let view = UnitView {
+                id: &te.id,
+                sides: terms
+                    .iter()
+                    .zip(&texts)
+                    .map(|((is_source, ls, _), text)| Side {
+                        language: &ls.language,
+                        is_source: *is_source,
+                        text,
                     })
+                    .collect(),
+                notes: te
+                    .notes
+                    .iter()
+                    .chain(te.lang_sets.iter().flat_map(|ls| &ls.notes))
+                    .chain(terms.iter().flat_map(|(_, _, tig)| &tig.notes))
+                    .map(String::as_str)
+                    .collect(),
+                state: None,
+            };
+
+            for (i, spans) in match_unit(matcher, &view) {
+                let (_, ls, tig) = terms[i];
+                let mut match_result = MatchResult::new(
+                    FileFormat::Tbx,
+                    &ls.language,
+                    texts[i].to_owned(),
+                    tig.position,
+                );
+                match_result.set_occurrences(spans);
+                match_result.similarity = matcher.similarity(&texts[i]);
+                match_result.path = self.path.to_owned();
+                match_result.unit_index = unit_index;
+                match_result.unit_id = te.id.to_owned();
+                for other in te.lang_sets.iter().filter(|l| l.language != ls.language) {
+                    match_result
+                        .other_languages
+                        .entry(other.language.to_owned())
+                        .or_default()
+                        .extend(
+                            other
+                                .tigs
+                                .iter()
+                                .map(|t| extract_text(&t.term, options.include_tags)),
+                        );
                 }
+                match_results.push(match_result);
             }
         }
         return match_results;
     }
+
+    /// The terms of a language set are checked together and joined with
+    /// `; `, so "lacks" means no synonym matches.
+    fn search_bilingual(
+        &self,
+        options: &SearchOptions,
+        query: &BilingualQuery,
+    ) -> Vec<BilingualMatch> {
+        let mut bilingual_matches = Vec::new();
+
+        for (unit_index, te) in self.term_entries.iter().enumerate() {
+            let side = |ls: &LangSet| {
+                let terms = ls
+                    .tigs
+                    .iter()
+                    .map(|t| extract_text(&t.term, options.include_tags))
+                    .collect::<Vec<_>>();
+                let position = ls.tigs.first().map(|t| t.position).unwrap_or_default();
+
+                let mut side =
+                    MatchResult::new(FileFormat::Tbx, &ls.language, terms.join("; "), position);
+                side.path = self.path.to_owned();
+                side.unit_index = unit_index;
+                side.unit_id = te.id.to_owned();
+                side
+            };
+
+            let source = match te.lang_sets.first() {
+                Some(source) => source,
+                None => continue,
+            };
+            for ls in te.lang_sets.iter().skip(1) {
+                if !options.scope.includes(&ls.language, false) {
+                    continue;
+                }
+                if let Some(bilingual_match) = query.check(side(source), side(ls)) {
+                    bilingual_matches.push(bilingual_match);
+                }
+            }
+        }
+
+        return bilingual_matches;
+    }
 }
 
+#[cfg(test)]
 mod tests {
     #[test]
     fn dummy_for_debug() {
-        let t = crate::tbx::TbxFile::new(&"./tests/lancom.tbx");
+        let t = crate::tbx::TbxFile::new("./tests/lancom.tbx").unwrap();
         dbg!(&t.term_entries);
-        assert!(1 != 2);
+        assert!(!t.term_entries.is_empty());
+    }
+
+    #[test]
+    fn keeps_synonyms_and_term_metadata() {
+        use crate::tbx::AdministrativeStatus;
+
+        let t = crate::tbx::TbxFile::new("./tests/lancom.tbx").unwrap();
+        let router = &t.term_entries[0];
+        assert_eq!(router.id, "c1");
+        assert_eq!(router.xrefs[0].target, "https://www.lancom-systems.com");
+
+        let german = &router.lang_sets[1];
+        assert_eq!(german.descrips[0].descrip_type, "definition");
+        assert_eq!(german.tigs.len(), 2);
+        assert_eq!(german.tigs[0].grammatical_gender(), Some("masculine"));
+        assert!(german.tigs[0].is_preferred());
+        assert_eq!(
+            crate::extract_text(&german.tigs[1].term, false),
+            "Wegewahlrechner"
+        );
+        assert!(german.tigs[1].is_forbidden());
+        assert_eq!(german.tigs[1].notes, vec!["Outdated, do not use."]);
+
+        let access_point = &t.term_entries[1].lang_sets[0].tigs[0];
+        assert_eq!(access_point.part_of_speech(), Some("noun"));
+        assert_eq!(
+            access_point.administrative_status(),
+            Some(AdministrativeStatus::Admitted)
+        );
     }
 }
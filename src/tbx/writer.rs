@@ -0,0 +1,306 @@
+use super::{Descrip, TbxFile, TermEntry, Tig, Xref};
+use crate::{segs_to_xml, Error, Result};
+use quick_xml::escape::{escape, partial_escape};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TbxDialect {
+    /// TBX-Basic on the ISO 30042:2008 `martif` core structure.
+    Basic2008,
+    /// TBX-Basic on the TBX v3 (ISO 30042:2019) core structure.
+    BasicV3,
+}
+
+struct Vocabulary {
+    entry: &'static str,
+    lang_set: &'static str,
+    term_group: &'static str,
+}
+
+impl TbxDialect {
+    fn vocabulary(&self) -> Vocabulary {
+        match self {
+            TbxDialect::Basic2008 => Vocabulary {
+                entry: "termEntry",
+                lang_set: "langSet",
+                term_group: "tig",
+            },
+            TbxDialect::BasicV3 => Vocabulary {
+                entry: "conceptEntry",
+                lang_set: "langSec",
+                term_group: "termSec",
+            },
+        }
+    }
+}
+
+impl TbxFile {
+    pub fn write<W: Write>(&self, mut writer: W, dialect: TbxDialect) -> Result<W> {
+        let document = self.render(dialect);
+
+        writer
+            .write_all(document.as_bytes())
+            .and_then(|_| writer.flush())
+            .map_err(|e| Error::Write {
+                path: self.path.to_owned(),
+                source: e,
+            })?;
+        return Ok(writer);
+    }
+
+    pub fn save(&self, path: &str, dialect: TbxDialect) -> Result<()> {
+        let file = File::create(path).map_err(|e| Error::Write {
+            path: path.to_owned(),
+            source: e,
+        })?;
+        self.write(BufWriter::new(file), dialect)?;
+        return Ok(());
+    }
+
+    fn render(&self, dialect: TbxDialect) -> String {
+        let language = self
+            .term_entries
+            .iter()
+            .flat_map(|te| &te.lang_sets)
+            .map(|ls| ls.language.as_str())
+            .next()
+            .unwrap_or("en");
+
+        let mut s = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        match dialect {
+            TbxDialect::Basic2008 => {
+                s.push_str("<!DOCTYPE martif SYSTEM \"TBXBasiccoreStructV02.dtd\">\n");
+                s.push_str(&format!(
+                    "<martif type=\"TBX-Basic-V1\" xml:lang=\"{}\">\n",
+                    escape(language)
+                ));
+                s.push_str("  <martifHeader>\n");
+                s.push_str("    <fileDesc><sourceDesc><p>");
+                s.push_str(env!("CARGO_PKG_NAME"));
+                s.push_str("</p></sourceDesc></fileDesc>\n");
+                s.push_str(
+                    "    <encodingDesc><p type=\"XCSURI\">TBXBasicXCSV02.xcs</p></encodingDesc>\n",
+                );
+                s.push_str("  </martifHeader>\n");
+            }
+            TbxDialect::BasicV3 => {
+                s.push_str(&format!(
+                    "<tbx type=\"TBX-Basic\" style=\"dca\" xml:lang=\"{}\" xmlns=\"urn:iso:std:iso:30042:ed-2\">\n",
+                    escape(language)
+                ));
+                s.push_str("  <tbxHeader>\n");
+                s.push_str("    <fileDesc><sourceDesc><p>");
+                s.push_str(env!("CARGO_PKG_NAME"));
+                s.push_str("</p></sourceDesc></fileDesc>\n");
+                s.push_str("  </tbxHeader>\n");
+            }
+        }
+
+        s.push_str("  <text>\n    <body>\n");
+        for (te, id) in self
+            .term_entries
+            .iter()
+            .zip(entry_ids(&self.term_entries, dialect))
+        {
+            write_entry(&mut s, te, &id, dialect);
+        }
+        s.push_str("    </body>\n  </text>\n");
+
+        match dialect {
+            TbxDialect::Basic2008 => s.push_str("</martif>\n"),
+            TbxDialect::BasicV3 => s.push_str("</tbx>\n"),
+        }
+
+        return s;
+    }
+}
+
+/// TBX v3 requires an id on every concept entry. Missing ones become
+/// `c<position>`, moved on to the next free number when the document
+/// already uses that id.
+fn entry_ids(term_entries: &[TermEntry], dialect: TbxDialect) -> Vec<String> {
+    let mut taken = term_entries
+        .iter()
+        .flat_map(|te| {
+            let tig_ids = te
+                .lang_sets
+                .iter()
+                .flat_map(|ls| &ls.tigs)
+                .map(|tig| &tig.id);
+            std::iter::once(&te.id).chain(tig_ids)
+        })
+        .filter(|id| !id.is_empty())
+        .cloned()
+        .collect::<HashSet<_>>();
+
+    let mut ids = Vec::new();
+    for (index, te) in term_entries.iter().enumerate() {
+        if !te.id.is_empty() || dialect != TbxDialect::BasicV3 {
+            ids.push(te.id.to_owned());
+            continue;
+        }
+
+        let mut n = index + 1;
+        while taken.contains(&format!("c{}", n)) {
+            n += 1;
+        }
+        let id = format!("c{}", n);
+        taken.insert(id.to_owned());
+        ids.push(id);
+    }
+
+    return ids;
+}
+
+fn write_entry(s: &mut String, te: &TermEntry, id: &str, dialect: TbxDialect) {
+    let vocabulary = dialect.vocabulary();
+
+    if id.is_empty() {
+        s.push_str(&format!("      <{}>\n", vocabulary.entry));
+    } else {
+        s.push_str(&format!(
+            "      <{} id=\"{}\">\n",
+            vocabulary.entry,
+            escape(id)
+        ));
+    }
+    write_descrips(s, "        ", &te.descrips);
+    write_notes(s, "        ", &te.notes);
+    write_xrefs(s, "        ", &te.xrefs);
+
+    for ls in &te.lang_sets {
+        s.push_str(&format!(
+            "        <{} xml:lang=\"{}\">\n",
+            vocabulary.lang_set,
+            escape(&ls.language)
+        ));
+        write_descrips(s, "          ", &ls.descrips);
+        write_notes(s, "          ", &ls.notes);
+        for tig in &ls.tigs {
+            write_tig(s, tig, vocabulary.term_group);
+        }
+        s.push_str(&format!("        </{}>\n", vocabulary.lang_set));
+    }
+
+    s.push_str(&format!("      </{}>\n", vocabulary.entry));
+}
+
+fn write_tig(s: &mut String, tig: &Tig, term_group: &str) {
+    if tig.id.is_empty() {
+        s.push_str(&format!("          <{}>\n", term_group));
+    } else {
+        s.push_str(&format!(
+            "          <{} id=\"{}\">\n",
+            term_group,
+            escape(&tig.id)
+        ));
+    }
+    s.push_str(&format!(
+        "            <term>{}</term>\n",
+        segs_to_xml(&tig.term)
+    ));
+    for term_note in &tig.term_notes {
+        s.push_str(&format!(
+            "            <termNote type=\"{}\">{}</termNote>\n",
+            escape(&term_note.note_type),
+            partial_escape(&term_note.value)
+        ));
+    }
+    write_descrips(s, "            ", &tig.descrips);
+    write_notes(s, "            ", &tig.notes);
+    write_xrefs(s, "            ", &tig.xrefs);
+    s.push_str(&format!("          </{}>\n", term_group));
+}
+
+fn write_notes(s: &mut String, indent: &str, notes: &[String]) {
+    for note in notes {
+        s.push_str(&format!(
+            "{}<note>{}</note>\n",
+            indent,
+            partial_escape(note)
+        ));
+    }
+}
+
+fn write_xrefs(s: &mut String, indent: &str, xrefs: &[Xref]) {
+    for xref in xrefs {
+        s.push_str(&format!(
+            "{}<xref type=\"{}\" target=\"{}\">{}</xref>\n",
+            indent,
+            escape(&xref.xref_type),
+            escape(&xref.target),
+            partial_escape(&xref.value)
+        ));
+    }
+}
+
+fn write_descrips(s: &mut String, indent: &str, descrips: &[Descrip]) {
+    for descrip in descrips {
+        s.push_str(&format!(
+            "{}<descrip type=\"{}\">{}</descrip>\n",
+            indent,
+            escape(&descrip.descrip_type),
+            partial_escape(&descrip.value)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TbxDialect;
+    use crate::tbx::TbxFile;
+
+    #[test]
+    fn writes_both_dialects() {
+        let t = TbxFile::new("./tests/lancom.tbx").unwrap();
+
+        for (dialect, root, entry) in [
+            (TbxDialect::Basic2008, "<martif", "<termEntry id=\"c1\">"),
+            (
+                TbxDialect::BasicV3,
+                "<tbx type=\"TBX-Basic\"",
+                "<conceptEntry id=\"c1\">",
+            ),
+        ] {
+            let written = String::from_utf8(t.write(Vec::new(), dialect).unwrap()).unwrap();
+            assert!(written.contains(root));
+            assert!(written.contains(entry));
+            assert!(written.contains("<descrip type=\"subjectField\">Networking</descrip>"));
+
+            let out =
+                std::env::temp_dir().join(format!("translation-xml-parser-{:?}.tbx", dialect));
+            t.save(out.to_str().unwrap(), dialect).unwrap();
+            let reread = TbxFile::new(out.to_str().unwrap()).unwrap();
+            assert_eq!(reread.term_entries.len(), t.term_entries.len());
+            assert_eq!(reread.term_entries[0].id, "c1");
+            assert_eq!(reread.term_entries[0].descrips, t.term_entries[0].descrips);
+            assert_eq!(
+                crate::extract_text(&reread.term_entries[1].lang_sets[1].tigs[0].term, false),
+                "Access Point"
+            );
+
+            let german = &reread.term_entries[0].lang_sets[1];
+            assert_eq!(german.descrips, t.term_entries[0].lang_sets[1].descrips);
+            assert_eq!(german.tigs.len(), 2);
+            assert_eq!(german.tigs[1].id, "t-de-2");
+            assert!(german.tigs[1].is_forbidden());
+            assert_eq!(reread.term_entries[0].xrefs, t.term_entries[0].xrefs);
+        }
+    }
+
+    #[test]
+    fn generated_ids_skip_existing_ones() {
+        let mut t = TbxFile::new("./tests/lancom.tbx").unwrap();
+        t.term_entries[0].id = String::new();
+        t.term_entries[1].id = "c1".to_string();
+        t.term_entries[1].lang_sets[0].tigs[0].id = "c2".to_string();
+
+        let out = std::env::temp_dir().join("translation-xml-parser-ids.tbx");
+        t.save(out.to_str().unwrap(), TbxDialect::BasicV3).unwrap();
+        let reread = TbxFile::new(out.to_str().unwrap()).unwrap();
+        assert_eq!(reread.term_entries[0].id, "c3");
+        assert_eq!(reread.term_entries[1].id, "c1");
+    }
+}
@@ -0,0 +1,521 @@
+use crate::fuzzy::{edit_distance, tokenize};
+use crate::tmx::TmxFile;
+use crate::xliff::{TransUnit, XliffFile};
+use crate::xlsx::TranslationXlsx;
+use crate::{extract_text, is_text_node, language_matches, IsTranslationXML, SegNode};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+/// A segment pair stored in a [`TranslationMemory`].
+#[derive(Debug, Clone, Serialize)]
+pub struct TmEntry {
+    pub source_language: String,
+    pub target_language: String,
+    pub source: Vec<Box<SegNode>>,
+    pub target: Vec<Box<SegNode>>,
+    /// Source texts of the segments before and after it in its document.
+    pub previous: Option<String>,
+    pub next: Option<String>,
+    /// Path of the file the entry was read from.
+    pub origin: String,
+}
+
+/// A segment to look up in a [`TranslationMemory`].
+#[derive(Debug, Default, Clone)]
+pub struct Lookup {
+    pub source: Vec<Box<SegNode>>,
+    /// Source texts of the segments around it. An exact match whose
+    /// neighbours are the same is a context match.
+    pub previous: Option<String>,
+    pub next: Option<String>,
+}
+
+impl Lookup {
+    /// A lookup of plain text without context.
+    pub fn new(text: &str) -> Lookup {
+        return Lookup {
+            source: vec![Box::new(SegNode::Text(text.to_owned()))],
+            ..Default::default()
+        };
+    }
+}
+
+/// The usual CAT tool match categories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchBand {
+    Fuzzy50To74,
+    Fuzzy75To84,
+    Fuzzy85To94,
+    Fuzzy95To99,
+    Exact,
+    Context,
+}
+
+impl MatchBand {
+    /// The band of a score as given in [`TmMatch::score`]; `None` below 50.
+    pub fn from_score(score: u8) -> Option<MatchBand> {
+        match score {
+            101.. => Some(MatchBand::Context),
+            100 => Some(MatchBand::Exact),
+            95..=99 => Some(MatchBand::Fuzzy95To99),
+            85..=94 => Some(MatchBand::Fuzzy85To94),
+            75..=84 => Some(MatchBand::Fuzzy75To84),
+            50..=74 => Some(MatchBand::Fuzzy50To74),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for MatchBand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let band = match self {
+            MatchBand::Context => "context match",
+            MatchBand::Exact => "100%",
+            MatchBand::Fuzzy95To99 => "95-99%",
+            MatchBand::Fuzzy85To94 => "85-94%",
+            MatchBand::Fuzzy75To84 => "75-84%",
+            MatchBand::Fuzzy50To74 => "50-74%",
+        };
+        write!(f, "{}", band)
+    }
+}
+
+/// One entry found by [`TranslationMemory::lookup`].
+#[derive(Debug, Clone, Serialize)]
+pub struct TmMatch<'a> {
+    pub entry: &'a TmEntry,
+    /// 101 for a context match, 100 for an exact match, otherwise the
+    /// fuzzy percentage after penalties.
+    pub score: u8,
+    pub band: MatchBand,
+}
+
+/// What segments are compared by: their words with every number masked as
+/// `0`, the numbers themselves, and the inline tags with the number of
+/// words before each.
+#[derive(Debug, Default)]
+struct Fingerprint {
+    words: Vec<String>,
+    numbers: Vec<String>,
+    tags: Vec<(String, usize)>,
+}
+
+impl Fingerprint {
+    fn new(segs: &[Box<SegNode>]) -> Fingerprint {
+        let mut fingerprint = Fingerprint::default();
+        let mut text = String::new();
+        fingerprint.walk(segs, &mut text);
+        fingerprint.words = tokenize(&text);
+        return fingerprint;
+    }
+
+    fn walk(&mut self, segs: &[Box<SegNode>], text: &mut String) {
+        for node in segs {
+            match node.as_ref() {
+                SegNode::Text(content) => mask_numbers(content, &mut self.numbers, text),
+                SegNode::OpenOrCloseNode {
+                    node_type, content, ..
+                } => {
+                    self.tags.push((node_type.to_owned(), tokenize(text).len()));
+                    // the content of native code is not text
+                    if is_text_node(node) {
+                        self.walk(content, text);
+                        self.tags
+                            .push((format!("/{}", node_type), tokenize(text).len()));
+                    }
+                }
+                SegNode::SelfClosingNode { node_type, .. } => {
+                    self.tags.push((node_type.to_owned(), tokenize(text).len()));
+                }
+            }
+        }
+    }
+}
+
+/// Copies `text` to `out` with each number, like `8.5` or `1,000`, replaced
+/// by `0`, collecting the numbers.
+fn mask_numbers(text: &str, numbers: &mut Vec<String>, out: &mut String) {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if !chars[i].is_ascii_digit() {
+            out.push(chars[i]);
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i < chars.len()
+            && (chars[i].is_ascii_digit()
+                || (matches!(chars[i], '.' | ',')
+                    && chars.get(i + 1).is_some_and(char::is_ascii_digit)))
+        {
+            i += 1;
+        }
+        numbers.push(chars[start..i].iter().collect());
+        out.push('0');
+    }
+}
+
+/// Whether a token is worth indexing: not punctuation or a masked number.
+fn is_word(token: &str) -> bool {
+    return token != "0" && token.chars().any(char::is_alphanumeric);
+}
+
+/// One unit of a document: its source and its translations by language.
+struct Unit<'a> {
+    source_language: &'a str,
+    source: &'a [Box<SegNode>],
+    targets: Vec<(&'a str, &'a [Box<SegNode>])>,
+}
+
+/// An offline translation memory built from TMX, XLIFF and XLSX files.
+/// Entries are indexed by language pair and by the words of their source.
+#[derive(Debug)]
+pub struct TranslationMemory {
+    pub entries: Vec<TmEntry>,
+    /// Points taken off a match for each inline tag that is missing, extra
+    /// or moved.
+    pub tag_penalty: u8,
+    /// Points taken off a match for each number that differs.
+    pub number_penalty: u8,
+    fingerprints: Vec<Fingerprint>,
+    pairs: HashMap<(String, String), Vec<usize>>,
+    words: HashMap<String, Vec<usize>>,
+}
+
+impl Default for TranslationMemory {
+    fn default() -> Self {
+        return TranslationMemory::new();
+    }
+}
+
+impl TranslationMemory {
+    pub fn new() -> TranslationMemory {
+        return TranslationMemory {
+            entries: Vec::new(),
+            tag_penalty: 1,
+            number_penalty: 2,
+            fingerprints: Vec::new(),
+            pairs: HashMap::new(),
+            words: HashMap::new(),
+        };
+    }
+
+    pub fn add(&mut self, entry: TmEntry) {
+        let index = self.entries.len();
+        let fingerprint = Fingerprint::new(&entry.source);
+
+        let words = fingerprint
+            .words
+            .iter()
+            .filter(|w| is_word(w))
+            .map(|w| w.to_lowercase())
+            .collect::<BTreeSet<_>>();
+        for word in words {
+            self.words.entry(word).or_default().push(index);
+        }
+        self.pairs
+            .entry((
+                entry.source_language.to_lowercase(),
+                entry.target_language.to_lowercase(),
+            ))
+            .or_default()
+            .push(index);
+
+        self.fingerprints.push(fingerprint);
+        self.entries.push(entry);
+    }
+
+    /// Adds every translated unit of a parsed file. TBX termbases hold no
+    /// segments and are skipped.
+    pub fn add_file(&mut self, file: &dyn IsTranslationXML) {
+        let file = file.as_any();
+        if let Some(tmx) = file.downcast_ref::<TmxFile>() {
+            self.add_tmx(tmx);
+        } else if let Some(xliff) = file.downcast_ref::<XliffFile>() {
+            self.add_xliff(xliff);
+        } else if let Some(xlsx) = file.downcast_ref::<TranslationXlsx>() {
+            self.add_xlsx(xlsx);
+        }
+    }
+
+    /// Adds one entry per target language of each `<tu>`. The source is the
+    /// `<tuv>` in the `srclang` of the unit or of the header.
+    pub fn add_tmx(&mut self, tmx: &TmxFile) {
+        let units = tmx
+            .tus
+            .iter()
+            .filter_map(|tu| {
+                let src_lang = tu.src_lang.as_deref().unwrap_or(&tmx.header.src_lang);
+                let source = tu.tuvs.iter().find(|tuv| {
+                    src_lang == "*all*" || tuv.language.eq_ignore_ascii_case(src_lang)
+                })?;
+                return Some(Unit {
+                    source_language: &source.language,
+                    source: &source.seg,
+                    targets: tu
+                        .tuvs
+                        .iter()
+                        .filter(|tuv| tuv.language != source.language)
+                        .map(|tuv| (tuv.language.as_str(), tuv.seg.as_slice()))
+                        .collect(),
+                });
+            })
+            .collect::<Vec<_>>();
+
+        self.add_units(&tmx.path, &units);
+    }
+
+    /// Adds the translated units, or `<mrk>` segments, of every `<file>`.
+    pub fn add_xliff(&mut self, xliff: &XliffFile) {
+        for file in &xliff.xfiles {
+            self.add_trans_units(
+                &xliff.path,
+                &file.src_language,
+                &file.tgt_language,
+                &file.trans_units,
+            );
+        }
+    }
+
+    pub fn add_xlsx(&mut self, xlsx: &TranslationXlsx) {
+        self.add_trans_units(
+            &xlsx.path,
+            &xlsx.src_language,
+            &xlsx.tgt_language,
+            &xlsx.trans_units,
+        );
+    }
+
+    fn add_trans_units(
+        &mut self,
+        origin: &str,
+        source_language: &str,
+        target_language: &str,
+        trans_units: &[TransUnit],
+    ) {
+        let mut pairs = Vec::new();
+        for tu in trans_units {
+            let segments = tu.segments();
+            if segments.is_empty() {
+                pairs.push((tu.source.as_slice(), tu.target.as_deref()));
+            } else {
+                for segment in segments {
+                    pairs.push((segment.source, segment.target));
+                }
+            }
+        }
+
+        let units = pairs
+            .into_iter()
+            .map(|(source, target)| Unit {
+                source_language,
+                source,
+                targets: target.map(|t| (target_language, t)).into_iter().collect(),
+            })
+            .collect::<Vec<_>>();
+        self.add_units(origin, &units);
+    }
+
+    /// Adds the units of one document in order, so that each entry gets its
+    /// neighbours as context. Empty translations are left out.
+    fn add_units(&mut self, origin: &str, units: &[Unit]) {
+        let texts = units
+            .iter()
+            .map(|u| extract_text(u.source, false))
+            .collect::<Vec<_>>();
+
+        for (i, unit) in units.iter().enumerate() {
+            for (target_language, target) in &unit.targets {
+                if target.is_empty() {
+                    continue;
+                }
+                self.add(TmEntry {
+                    source_language: unit.source_language.to_owned(),
+                    target_language: target_language.to_string(),
+                    source: unit.source.to_vec(),
+                    target: target.to_vec(),
+                    previous: i.checked_sub(1).map(|p| texts[p].to_owned()),
+                    next: texts.get(i + 1).cloned(),
+                    origin: origin.to_owned(),
+                });
+            }
+        }
+    }
+
+    /// Entries translating `lookup` from `source_language` into
+    /// `target_language` with a score of at least `min_score`, best first.
+    /// Languages are BCP-47 ranges, so `en` finds `en-US` as well. Scores
+    /// below 50 are never reported.
+    pub fn lookup(
+        &self,
+        lookup: &Lookup,
+        source_language: &str,
+        target_language: &str,
+        min_score: u8,
+    ) -> Vec<TmMatch<'_>> {
+        let wanted = Fingerprint::new(&lookup.source);
+        if wanted.words.is_empty() && wanted.tags.is_empty() {
+            return Vec::new();
+        }
+        let min_score = min_score.max(50);
+
+        let in_pair = self
+            .pairs
+            .iter()
+            .filter(|((source, target), _)| {
+                language_matches(source_language, source)
+                    && language_matches(target_language, target)
+            })
+            .flat_map(|(_, indices)| indices.iter().copied())
+            .collect::<HashSet<_>>();
+
+        // a lookup without words is compared with the whole language pair
+        let words = wanted
+            .words
+            .iter()
+            .filter(|w| is_word(w))
+            .map(|w| w.to_lowercase())
+            .collect::<Vec<_>>();
+        let candidates = if words.is_empty() {
+            in_pair.into_iter().collect::<BTreeSet<_>>()
+        } else {
+            words
+                .iter()
+                .filter_map(|w| self.words.get(w))
+                .flatten()
+                .copied()
+                .filter(|i| in_pair.contains(i))
+                .collect::<BTreeSet<_>>()
+        };
+
+        let mut found = Vec::new();
+        for index in candidates {
+            let fingerprint = &self.fingerprints[index];
+            // the word counts alone bound the similarity
+            let (shorter, longer) = if wanted.words.len() < fingerprint.words.len() {
+                (wanted.words.len(), fingerprint.words.len())
+            } else {
+                (fingerprint.words.len(), wanted.words.len())
+            };
+            if longer != 0 && 100 * shorter < usize::from(min_score) * longer {
+                continue;
+            }
+
+            let entry = &self.entries[index];
+            let mut score = self.score(&wanted, fingerprint);
+            if score == 100 && is_same_context(lookup, entry) {
+                score = 101;
+            }
+            if score < min_score {
+                continue;
+            }
+            if let Some(band) = MatchBand::from_score(score) {
+                found.push(TmMatch { entry, score, band });
+            }
+        }
+
+        found.sort_by_key(|m| std::cmp::Reverse(m.score));
+        return found;
+    }
+
+    /// Word similarity in percent, less the tag and number penalties. Only
+    /// identical segments score 100.
+    fn score(&self, wanted: &Fingerprint, found: &Fingerprint) -> u8 {
+        let longest = wanted.words.len().max(found.words.len());
+        let distance = edit_distance(&wanted.words, &found.words);
+        let similarity = (100 * (longest - distance))
+            .checked_div(longest)
+            .unwrap_or(100);
+
+        let penalty = usize::from(self.tag_penalty) * edit_distance(&wanted.tags, &found.tags)
+            + usize::from(self.number_penalty) * edit_distance(&wanted.numbers, &found.numbers);
+        if penalty == 0 {
+            return similarity as u8;
+        }
+        return similarity.saturating_sub(penalty).min(99) as u8;
+    }
+}
+
+/// Whether the lookup has context and all of it matches that of `entry`.
+fn is_same_context(lookup: &Lookup, entry: &TmEntry) -> bool {
+    if lookup.previous.is_none() && lookup.next.is_none() {
+        return false;
+    }
+    return lookup
+        .previous
+        .as_ref()
+        .is_none_or(|p| entry.previous.as_ref() == Some(p))
+        && lookup
+            .next
+            .as_ref()
+            .is_none_or(|n| entry.next.as_ref() == Some(n));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lookup, MatchBand, TranslationMemory};
+    use crate::extract_text;
+
+    #[test]
+    fn ranks_matches_by_percentage() {
+        let mut tm = TranslationMemory::new();
+        tm.add_tmx(&crate::tmx::TmxFile::new("./tests/CITIC.tmx").unwrap());
+        let best = |lookup: &Lookup| {
+            let found = tm.lookup(lookup, "en", "zh", 50);
+            found.first().map(|m| (m.score, m.band))
+        };
+
+        let mut lookup = Lookup::new("Please keep your account password safe & secret.");
+        assert_eq!(best(&lookup), Some((100, MatchBand::Exact)));
+        let found = tm.lookup(&lookup, "en", "zh", 50);
+        assert_eq!(
+            extract_text(&found[0].entry.target, false),
+            "请妥善保管您的账户密码。"
+        );
+
+        lookup.previous = Some("Total assets reached RMB 8.5 trillion.".to_string());
+        assert_eq!(best(&lookup), Some((101, MatchBand::Context)));
+
+        let lookup = Lookup::new("The bank opened a new account for the customer.");
+        assert_eq!(best(&lookup), Some((90, MatchBand::Fuzzy85To94)));
+
+        // a different number and two missing tags
+        let lookup = Lookup::new("Total assets reached RMB 9.2 trillion.");
+        assert_eq!(best(&lookup), Some((96, MatchBand::Fuzzy95To99)));
+
+        let lookup = Lookup::new("The bank opened a new account for the client.");
+        assert!(tm.lookup(&lookup, "en", "de", 50).is_empty());
+        assert!(tm
+            .lookup(&Lookup::new("Nothing alike"), "en", "zh", 50)
+            .is_empty());
+    }
+
+    #[test]
+    fn reads_xliff_segments() {
+        let path = std::path::Path::new("./tests/approval.sdlxliff");
+        let mut tm = TranslationMemory::new();
+        tm.add_file(crate::read_file_with_parser(path).unwrap().as_ref());
+
+        let found = tm.lookup(
+            &Lookup::new("The approval state is shown here."),
+            "en",
+            "de",
+            75,
+        );
+        assert_eq!(found.len(), 1);
+        // 85 for the words, less the missing <g> pair
+        assert_eq!(
+            (found[0].score, found[0].band),
+            (83, MatchBand::Fuzzy75To84)
+        );
+        assert_eq!(
+            extract_text(&found[0].entry.target, false),
+            "Der Genehmigungsstatus wird hier angezeigt."
+        );
+    }
+}
@@ -1,160 +1,519 @@
-use crate::{extract_text, GetMeta, MatchResult, MetaInfo, SearchInFile, SearchString, SegNode};
-use quick_xml::events::Event;
-use quick_xml::reader::Reader;
+use crate::{
+    extract_text, match_unit, BilingualMatch, BilingualQuery, FileFormat, GetMeta, MatchResult,
+    MetaInfo, Position, Result, SearchInFile, SearchOptions, SearchSource, SearchString, SegNode,
+    Side, UnitView,
+};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+mod reader;
+mod writer;
+
+pub use reader::TmxReader;
+pub use writer::{segs_to_tmx, TmxWriter};
+
 #[derive(Debug, Clone)]
 pub struct TmxFile {
     pub path: String,
+    pub header: TmxHeader,
     pub tus: Vec<TU>,
-    raw_content: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TmxHeader {
+    pub creation_tool: String,
+    pub creation_tool_version: String,
+    pub seg_type: String,
+    pub o_tmf: String,
+    pub admin_lang: String,
+    pub src_lang: String,
+    pub data_type: String,
+    pub o_encoding: Option<String>,
+    pub attributes: TmxAttributes,
+    pub props: Vec<Prop>,
+    pub notes: Vec<String>,
+}
+
+impl Default for TmxHeader {
+    fn default() -> Self {
+        return TmxHeader {
+            creation_tool: env!("CARGO_PKG_NAME").to_string(),
+            creation_tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            seg_type: "sentence".to_string(),
+            o_tmf: env!("CARGO_PKG_NAME").to_string(),
+            admin_lang: "en-us".to_string(),
+            src_lang: "*all*".to_string(),
+            data_type: "plaintext".to_string(),
+            o_encoding: None,
+            attributes: TmxAttributes::default(),
+            props: Vec::new(),
+            notes: Vec::new(),
+        };
+    }
+}
+
+/// The dates, ids and usage counters TMX allows on `<header>`, `<tu>` and
+/// `<tuv>`. Dates are kept as written, normally `YYYYMMDDThhmmssZ`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TmxAttributes {
+    pub creation_date: Option<String>,
+    pub creation_id: Option<String>,
+    pub change_date: Option<String>,
+    pub change_id: Option<String>,
+    pub usage_count: Option<u32>,
+    pub last_usage_date: Option<String>,
+}
+
+impl TmxAttributes {
+    fn from_attributes(attributes: &HashMap<String, String>) -> TmxAttributes {
+        return TmxAttributes {
+            creation_date: attributes.get("creationdate").cloned(),
+            creation_id: attributes.get("creationid").cloned(),
+            change_date: attributes.get("changedate").cloned(),
+            change_id: attributes.get("changeid").cloned(),
+            usage_count: attributes.get("usagecount").and_then(|c| c.parse().ok()),
+            last_usage_date: attributes.get("lastusagedate").cloned(),
+        };
+    }
+
+    /// `changedate`, or `creationdate` for entries never changed.
+    pub fn last_modified(&self) -> Option<&str> {
+        return self
+            .change_date
+            .as_deref()
+            .or(self.creation_date.as_deref());
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Prop {
+    pub prop_type: String,
+    pub value: String,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct TU {
     pub tuid: String,
+    pub src_lang: Option<String>,
+    pub attributes: TmxAttributes,
     pub tuvs: Vec<TUV>,
+    pub props: Vec<Prop>,
+    pub notes: Vec<String>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct TUV {
     pub language: String,
+    pub attributes: TmxAttributes,
     pub seg: Vec<Box<SegNode>>,
+    pub props: Vec<Prop>,
+    pub notes: Vec<String>,
+    /// Where the `<seg>` content starts in the document.
+    pub position: Position,
+}
+
+impl Prop {
+    pub fn new(prop_type: &str, value: &str) -> Prop {
+        return Prop {
+            prop_type: prop_type.to_owned(),
+            value: value.to_owned(),
+        };
+    }
+}
+
+impl TU {
+    pub fn new(tuid: &str) -> TU {
+        return TU {
+            tuid: tuid.to_owned(),
+            ..Default::default()
+        };
+    }
+
+    pub fn with_tuv(mut self, tuv: TUV) -> TU {
+        self.tuvs.push(tuv);
+        return self;
+    }
+
+    /// Values of the `<prop>`s of `prop_type` on the unit and its variants.
+    pub fn prop_values<'a>(&'a self, prop_type: &'a str) -> impl Iterator<Item = &'a str> {
+        return self
+            .props
+            .iter()
+            .chain(self.tuvs.iter().flat_map(|tuv| &tuv.props))
+            .filter(move |p| p.prop_type == prop_type)
+            .map(|p| p.value.as_str());
+    }
+
+    /// The latest modification date of the unit or any of its variants.
+    pub fn last_modified(&self) -> Option<&str> {
+        return self
+            .tuvs
+            .iter()
+            .map(|tuv| &tuv.attributes)
+            .chain([&self.attributes])
+            .filter_map(|a| a.last_modified())
+            .max_by_key(|d| normalize_date(d));
+    }
+
+    /// Whether the unit passes the TMX filters of `options`.
+    pub fn matches_filters(&self, options: &SearchOptions) -> bool {
+        let has_props = options.props.iter().all(|wanted| {
+            self.prop_values(&wanted.prop_type)
+                .any(|v| v == wanted.value)
+        });
+        if !has_props {
+            return false;
+        }
+
+        if options.changed_after.is_none() && options.changed_before.is_none() {
+            return true;
+        }
+        let modified = match self.last_modified() {
+            Some(date) => normalize_date(date),
+            None => return false,
+        };
+        let after = options
+            .changed_after
+            .as_deref()
+            .is_none_or(|d| modified >= normalize_date(d));
+        let before = options
+            .changed_before
+            .as_deref()
+            .is_none_or(|d| modified < normalize_date(d));
+        return after && before;
+    }
+}
+
+/// Drops the separators of ISO 8601 dates, so `2022-03-15` compares
+/// correctly against the TMX form `20220315T081502Z`.
+fn normalize_date(date: &str) -> String {
+    return date.chars().filter(|c| *c != '-' && *c != ':').collect();
+}
+
+impl TUV {
+    pub fn new(language: &str, seg: Vec<Box<SegNode>>) -> TUV {
+        return TUV {
+            language: language.to_lowercase(),
+            seg,
+            ..Default::default()
+        };
+    }
+
+    pub fn from_text(language: &str, text: &str) -> TUV {
+        return TUV::new(language, vec![Box::new(SegNode::Text(text.to_owned()))]);
+    }
 }
 
 impl TmxFile {
-    pub fn new(path: &str) -> TmxFile {
-        let content = crate::read_to_string(path);
+    /// Reads the whole memory. Use [`TmxReader`] to go through large files
+    /// one unit at a time instead.
+    pub fn new(path: &str) -> Result<TmxFile> {
+        let tmx_reader = TmxReader::from_path(path)?;
+        let header = tmx_reader.header().to_owned();
 
-        let mut tmx_file = TmxFile {
+        return Ok(TmxFile {
             path: path.to_string(),
-            tus: Vec::new(),
-            raw_content: content,
+            header,
+            tus: tmx_reader.collect::<Result<Vec<TU>>>()?,
+        });
+    }
+
+    /// Builds a TMX in memory, e.g. when converting from another format.
+    pub fn from_tus(header: TmxHeader, tus: Vec<TU>) -> TmxFile {
+        return TmxFile {
+            path: String::new(),
+            header,
+            tus,
         };
-        tmx_file.parse();
-        return tmx_file;
-    }
-
-    fn parse(&mut self) {
-        let mut buf = Vec::new();
-
-        let mut cur_tu = TU::default();
-        let mut cur_tuv = TUV::default();
-        let mut cur_seg: Vec<Box<SegNode>>;
-
-        let mut reader = Reader::from_str(&self.raw_content);
-
-        loop {
-            match reader.read_event_into(&mut buf) {
-                Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-                Ok(Event::Start(e)) => match e.name().as_ref() {
-                    b"tu" => {
-                        cur_tu.tuid = crate::get_attributes(&reader, &e)
-                            .get("tuid")
-                            .unwrap_or(&"".to_string())
-                            .to_owned()
-                    }
-                    b"tuv" => {
-                        cur_tuv.language = crate::get_attributes(&reader, &e)
-                            .get("xml:lang")
-                            .unwrap_or(&"".to_string())
-                            .to_owned()
-                            .to_lowercase()
-                    }
-
-                    b"seg" => {
-                        cur_seg = SegNode::parse_inline(&mut reader, &mut buf);
-                        if cur_seg.len() != 0 {
-                            cur_tuv.seg = cur_seg;
-                        }
-                    }
-                    _ => (),
-                },
-                Ok(Event::End(e)) => match e.name().as_ref() {
-                    b"tuv" => {
-                        if cur_tuv.seg.len() != 0 {
-                            cur_tu.tuvs.push(cur_tuv)
-                        }
-
-                        cur_tuv = TUV::default();
-                    }
-                    b"tu" => {
-                        if cur_tu.tuvs.len() != 0 {
-                            self.tus.push(cur_tu);
-                        }
-
-                        cur_tu = TU::default();
-                    }
-                    _ => (),
-                },
-                Ok(Event::Eof) => break,
-                _ => (),
-            }
-            buf.clear()
-        }
     }
 }
 
 impl GetMeta for TmxFile {
-    fn get_meta(&self) -> MetaInfo {
+    fn get_meta(&self) -> MetaInfo<'_> {
         let mut languages = HashMap::new();
 
-        for tuv in self
-            .tus
-            .iter()
-            .map(|tu| &tu.tuvs)
-            .flatten()
-            .collect::<Vec<_>>()
-        {
+        for tuv in self.tus.iter().flat_map(|tu| &tu.tuvs).collect::<Vec<_>>() {
             let cur_lang = tuv.language.as_str();
             let acc_len = languages.get(cur_lang).unwrap_or(&0).to_owned();
             languages.insert(cur_lang, acc_len + 1);
         }
 
-        return MetaInfo { languages };
+        return MetaInfo {
+            languages,
+            status: None,
+        };
     }
 
     fn get_filename(&self) -> String {
         return self.path.to_owned();
     }
+
+    fn get_format(&self) -> FileFormat {
+        return FileFormat::Tmx;
+    }
 }
 
 impl SearchInFile for TmxFile {
     fn search_in_file(
         &self,
-        include_tags: bool,
-        matcher: &Box<dyn SearchString>,
+        options: &SearchOptions,
+        matcher: &dyn SearchString,
     ) -> Vec<MatchResult> {
         let mut match_results = Vec::new();
 
-        for tu in &self.tus {
-            for tuv in &tu.tuvs {
-                let cur_tuv = extract_text(&tuv.seg, include_tags);
-                if let Some(match_result) = matcher.match_string(&cur_tuv) {
-                    match_results.push(MatchResult {
-                        text: cur_tuv,
-                        matched: match_result,
-                        extra: tu
-                            .tuvs
-                            .iter()
-                            .filter(|v| v.language != tuv.language)
-                            .map(|v| v.seg.iter().collect::<String>())
-                            .collect::<Vec<String>>(),
-                    })
-                }
-            }
+        let from = self.search_source();
+        for (unit_index, tu) in self.tus.iter().enumerate() {
+            search_in_tu(&from, unit_index, tu, options, matcher, &mut match_results);
         }
 
         return match_results;
     }
+
+    fn search_bilingual(
+        &self,
+        options: &SearchOptions,
+        query: &BilingualQuery,
+    ) -> Vec<BilingualMatch> {
+        let mut bilingual_matches = Vec::new();
+
+        let from = self.search_source();
+        for (unit_index, tu) in self.tus.iter().enumerate() {
+            search_bilingual_in_tu(
+                &from,
+                unit_index,
+                tu,
+                options,
+                query,
+                &mut bilingual_matches,
+            );
+        }
+
+        return bilingual_matches;
+    }
+}
+
+impl TmxFile {
+    fn search_source(&self) -> SearchSource<'_> {
+        return SearchSource {
+            path: &self.path,
+            format: FileFormat::Tmx,
+            file_index: 0,
+            source_language: &self.header.src_lang,
+            target_language: "",
+        };
+    }
 }
 
+/// Checks the source `<tuv>` of one unit against each of its other
+/// languages; see [`search_in_tu`] for `from` and `unit_index`. Units
+/// without a source `<tuv>` never match.
+pub fn search_bilingual_in_tu(
+    from: &SearchSource,
+    unit_index: usize,
+    tu: &TU,
+    options: &SearchOptions,
+    query: &BilingualQuery,
+    bilingual_matches: &mut Vec<BilingualMatch>,
+) {
+    if !tu.matches_filters(options) {
+        return;
+    }
+
+    let src_lang = tu.src_lang.as_deref().unwrap_or(from.source_language);
+    let source_tuv = match tu
+        .tuvs
+        .iter()
+        .find(|v| v.language.eq_ignore_ascii_case(src_lang))
+    {
+        Some(tuv) => tuv,
+        None => return,
+    };
+    let side = |tuv: &TUV| {
+        let mut side = MatchResult::new(
+            from.format,
+            &tuv.language,
+            extract_text(&tuv.seg, options.include_tags),
+            tuv.position,
+        );
+        side.path = from.path.to_owned();
+        side.unit_index = unit_index;
+        side.unit_id = tu.tuid.to_owned();
+        side
+    };
+
+    for tuv in &tu.tuvs {
+        if tuv.language.eq_ignore_ascii_case(src_lang)
+            || !options.scope.includes(&tuv.language, false)
+        {
+            continue;
+        }
+        if let Some(bilingual_match) = query.check(side(source_tuv), side(tuv)) {
+            bilingual_matches.push(bilingual_match);
+        }
+    }
+}
+
+/// Searches one unit, e.g. as it comes out of a [`TmxReader`]. `unit_index`
+/// is the position of the unit in its file; the source language of `from`
+/// is the `srclang` of the header, used when the unit has none.
+pub fn search_in_tu(
+    from: &SearchSource,
+    unit_index: usize,
+    tu: &TU,
+    options: &SearchOptions,
+    matcher: &dyn SearchString,
+    match_results: &mut Vec<MatchResult>,
+) {
+    if !tu.matches_filters(options) {
+        return;
+    }
+
+    let src_lang = tu.src_lang.as_deref().unwrap_or(from.source_language);
+    let is_source = |tuv: &TUV| tuv.language.eq_ignore_ascii_case(src_lang);
+
+    let tuvs = tu
+        .tuvs
+        .iter()
+        .filter(|tuv| options.scope.includes(&tuv.language, is_source(tuv)))
+        .collect::<Vec<_>>();
+    let texts = tuvs
+        .iter()
+        .map(|tuv| extract_text(&tuv.seg, options.include_tags))
+        .collect::<Vec<_>>();
+    let view = UnitView {
+        id: &tu.tuid,
+        sides: tuvs
+            .iter()
+            .zip(&texts)
+            .map(|(tuv, text)| Side {
+                language: &tuv.language,
+                is_source: is_source(tuv),
+                text,
+            })
+            .collect(),
+        notes: tu
+            .notes
+            .iter()
+            .chain(tu.tuvs.iter().flat_map(|tuv| &tuv.notes))
+            .map(String::as_str)
+            .collect(),
+        state: None,
+    };
+
+    for (i, spans) in match_unit(matcher, &view) {
+        let tuv = tuvs[i];
+        let mut match_result = MatchResult::new(
+            from.format,
+            &tuv.language,
+            texts[i].to_owned(),
+            tuv.position,
+        );
+        match_result.set_occurrences(spans);
+        match_result.similarity = matcher.similarity(&texts[i]);
+        match_result.path = from.path.to_owned();
+        match_result.unit_index = unit_index;
+        match_result.unit_id = tu.tuid.to_owned();
+        for other in tu.tuvs.iter().filter(|v| v.language != tuv.language) {
+            match_result
+                .other_languages
+                .entry(other.language.to_owned())
+                .or_default()
+                .push(extract_text(&other.seg, options.include_tags));
+        }
+        match_results.push(match_result);
+    }
+}
+
+#[cfg(test)]
 mod tests {
     #[test]
     fn dummy_for_debug() {
-        let t = crate::tmx::TmxFile::new(&"./tests/CITIC.tmx");
-        dbg!(t.tus);
-        assert!(1 != 2);
+        let t = crate::tmx::TmxFile::new("./tests/CITIC.tmx").unwrap();
+        dbg!(&t.tus);
+        assert!(!t.tus.is_empty());
+    }
+
+    #[test]
+    fn scopes_search_to_sides_and_languages() {
+        use crate::{SearchInFile, SearchOptions, SearchScope};
+
+        let t = crate::tmx::TmxFile::new("./tests/CITIC.tmx").unwrap();
+        let matcher = "账户".to_string();
+        let hits = |scope| {
+            let options = SearchOptions {
+                scope,
+                ..Default::default()
+            };
+            t.search_in_file(&options, &matcher).len()
+        };
+
+        // srclang="en-US" in the header
+        assert_eq!(hits(SearchScope::Source), 0);
+        assert_eq!(hits(SearchScope::Target), 2);
+        assert_eq!(hits(SearchScope::Languages(vec!["zh".to_string()])), 2);
+        assert_eq!(hits(SearchScope::Languages(vec!["zh-CN".to_string()])), 2);
+        assert_eq!(hits(SearchScope::Languages(vec!["zh-TW".to_string()])), 0);
+    }
+
+    #[test]
+    fn keeps_header_props_notes_and_dates() {
+        use crate::tmx::{Prop, TmxFile};
+        use crate::SearchInFile;
+
+        let t = TmxFile::new("./tests/CITIC.tmx").unwrap();
+        assert_eq!(t.header.creation_tool, "SDL Language Platform");
+        assert_eq!(t.header.src_lang, "en-US");
+        assert_eq!(
+            t.header.props,
+            vec![Prop::new("x-Recognizers", "RecognizeAll")]
+        );
+
+        let tu = &t.tus[0];
+        assert_eq!(tu.attributes.change_id.as_deref(), Some("CITIC\\reviewer"));
+        assert_eq!(tu.attributes.usage_count, Some(3));
+        assert_eq!(tu.last_modified(), Some("20220316T093011Z"));
+        assert_eq!(
+            tu.prop_values("x-Project").collect::<Vec<_>>(),
+            ["Annual Report"]
+        );
+        assert_eq!(tu.notes, ["Checked against the 2021 glossary."]);
+
+        let matcher = "account".to_string();
+        let mut options = crate::SearchOptions::default();
+        assert_eq!(t.search_in_file(&options, &matcher).len(), 2);
+
+        options.props = vec![Prop::new("x-Client", "CITIC")];
+        assert_eq!(t.search_in_file(&options, &matcher).len(), 1);
+
+        options.props.clear();
+        options.changed_after = Some("2022-04-01".to_string());
+        assert_eq!(t.search_in_file(&options, &matcher).len(), 1);
+        options.changed_before = Some("2022-04-01".to_string());
+        assert!(t.search_in_file(&options, &matcher).is_empty());
+
+        let written = String::from_utf8(t.write(Vec::new()).unwrap()).unwrap();
+        assert!(written.contains("changedate=\"20220316T093011Z\" changeid=\"CITIC\\reviewer\""));
+        assert!(written.contains("<prop type=\"x-Recognizers\">RecognizeAll</prop>"));
+        assert!(written.contains("<note>Checked against the 2021 glossary.</note>"));
+    }
+
+    #[test]
+    fn keeps_all_inline_elements() {
+        let t = crate::tmx::TmxFile::new("./tests/inline.tmx").unwrap();
+        let seg = &t.tus[0].tuvs[0].seg;
+
+        assert_eq!(
+            crate::extract_text(seg, false),
+            "Bold and nested text, Link titlea link, A cat and  R&D ."
+        );
+        assert_eq!(
+            crate::extract_text(seg, true),
+            "<b>Bold and nested text, <a title=\"Link title\">a link</a>, <img alt=\"A cat\"/> and {\\b} R&D ."
+        );
+
+        let written = crate::segs_to_xml(seg);
+        assert!(written.contains("<hi type=\"emphasis\">and <hi>nested</hi></hi>"));
+        assert!(written.contains("<bpt i=\"1\">&lt;a title=\"<sub>Link title</sub>\"&gt;</bpt>"));
+        assert!(written.contains("<ut>{\\b}</ut>"));
     }
 }
@@ -0,0 +1,344 @@
+use super::{Prop, TmxAttributes, TmxHeader, TU, TUV};
+use crate::{Error, Position, Result, SegNode};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::reader::Reader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Reads a TMX document one `<tu>` at a time, so memories of any size can
+/// be searched, counted or converted in constant memory. The header is read
+/// up front.
+pub struct TmxReader<R: BufRead> {
+    reader: Reader<PositionReader<R>>,
+    buf: Vec<u8>,
+    path: Option<String>,
+    header: TmxHeader,
+    done: bool,
+}
+
+impl TmxReader<BufReader<File>> {
+    pub fn from_path(path: &str) -> Result<TmxReader<BufReader<File>>> {
+        let file = File::open(path).map_err(|e| Error::Io {
+            path: path.to_owned(),
+            source: e,
+        })?;
+
+        let mut tmx_reader = TmxReader {
+            reader: Reader::from_reader(PositionReader::new(BufReader::new(file))),
+            buf: Vec::new(),
+            path: Some(path.to_owned()),
+            header: TmxHeader::default(),
+            done: false,
+        };
+        tmx_reader
+            .read_header()
+            .map_err(|e| e.locate_in_file(path))?;
+        return Ok(tmx_reader);
+    }
+}
+
+impl<R: BufRead> TmxReader<R> {
+    pub fn new(inner: R) -> Result<TmxReader<R>> {
+        let mut tmx_reader = TmxReader {
+            reader: Reader::from_reader(PositionReader::new(inner)),
+            buf: Vec::new(),
+            path: None,
+            header: TmxHeader::default(),
+            done: false,
+        };
+        tmx_reader.read_header()?;
+        return Ok(tmx_reader);
+    }
+
+    pub fn header(&self) -> &TmxHeader {
+        return &self.header;
+    }
+
+    /// Reads everything up to `<body>`.
+    fn read_header(&mut self) -> Result<()> {
+        let reader = &mut self.reader;
+
+        loop {
+            match reader.read_event_into(&mut self.buf) {
+                Err(e) => return Err(Error::xml(reader.buffer_position(), e)),
+                Ok(Event::Start(e)) => match e.name().as_ref() {
+                    b"header" => {
+                        self.header = parse_header(&crate::get_attributes(reader, &e)?);
+                    }
+                    b"prop" => {
+                        let prop = read_prop(reader, &e)?;
+                        self.header.props.push(prop);
+                    }
+                    b"note" => {
+                        let note = crate::read_element_text(reader, &e)?;
+                        self.header.notes.push(note);
+                    }
+                    b"body" => break,
+                    _ => (),
+                },
+                Ok(Event::Empty(e)) => match e.name().as_ref() {
+                    b"header" => {
+                        self.header = parse_header(&crate::get_attributes(reader, &e)?);
+                    }
+                    b"body" => {
+                        self.done = true;
+                        break;
+                    }
+                    _ => (),
+                },
+                Ok(Event::Eof) => {
+                    self.done = true;
+                    break;
+                }
+                _ => (),
+            }
+            self.buf.clear();
+        }
+        self.buf.clear();
+
+        return Ok(());
+    }
+
+    fn read_tu(&mut self) -> Result<Option<TU>> {
+        let reader = &mut self.reader;
+
+        let mut cur_tu = TU::default();
+        let mut cur_tuv = TUV::default();
+        let mut in_tuv = false;
+
+        loop {
+            match reader.read_event_into(&mut self.buf) {
+                Err(e) => return Err(Error::xml(reader.buffer_position(), e)),
+                Ok(Event::Start(e)) => match e.name().as_ref() {
+                    b"tu" => {
+                        let attributes = crate::get_attributes(reader, &e)?;
+                        cur_tu.tuid = attributes.get("tuid").unwrap_or(&"".to_string()).to_owned();
+                        cur_tu.src_lang = attributes.get("srclang").cloned();
+                        cur_tu.attributes = TmxAttributes::from_attributes(&attributes);
+                    }
+                    b"tuv" => {
+                        let attributes = crate::get_attributes(reader, &e)?;
+                        cur_tuv.language = attributes
+                            .get("xml:lang")
+                            .or_else(|| attributes.get("lang"))
+                            .unwrap_or(&"".to_string())
+                            .to_owned()
+                            .to_lowercase();
+                        cur_tuv.attributes = TmxAttributes::from_attributes(&attributes);
+                        in_tuv = true;
+                    }
+                    b"prop" => {
+                        let prop = read_prop(reader, &e)?;
+                        if in_tuv {
+                            cur_tuv.props.push(prop);
+                        } else {
+                            cur_tu.props.push(prop);
+                        }
+                    }
+                    b"note" => {
+                        let note = crate::read_element_text(reader, &e)?;
+                        if in_tuv {
+                            cur_tuv.notes.push(note);
+                        } else {
+                            cur_tu.notes.push(note);
+                        }
+                    }
+                    b"seg" => {
+                        cur_tuv.position = reader.get_ref().position;
+                        self.buf.clear();
+                        cur_tuv.seg = SegNode::parse_inline(reader, &mut self.buf)?;
+                    }
+                    _ => (),
+                },
+                Ok(Event::End(e)) => match e.name().as_ref() {
+                    b"tuv" => {
+                        if !cur_tuv.seg.is_empty() {
+                            cur_tu.tuvs.push(cur_tuv)
+                        }
+
+                        cur_tuv = TUV::default();
+                        in_tuv = false;
+                    }
+                    b"tu" => {
+                        if !cur_tu.tuvs.is_empty() {
+                            self.buf.clear();
+                            return Ok(Some(cur_tu));
+                        }
+
+                        cur_tu = TU::default();
+                    }
+                    b"body" => return Ok(None),
+                    _ => (),
+                },
+                Ok(Event::Eof) => return Ok(None),
+                _ => (),
+            }
+            self.buf.clear();
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TmxReader<R> {
+    type Item = Result<TU>;
+
+    fn next(&mut self) -> Option<Result<TU>> {
+        if self.done {
+            return None;
+        }
+
+        let tu = self.read_tu();
+        if !matches!(tu, Ok(Some(..))) {
+            self.done = true;
+        }
+
+        return match tu {
+            Ok(tu) => tu.map(Ok),
+            Err(e) => match &self.path {
+                Some(path) => Some(Err(e.locate_in_file(path))),
+                None => Some(Err(e)),
+            },
+        };
+    }
+}
+
+/// Keeps track of the line and column of everything read so far, so units
+/// can be located without holding the document in memory.
+struct PositionReader<R> {
+    inner: R,
+    position: Position,
+}
+
+impl<R: BufRead> PositionReader<R> {
+    fn new(inner: R) -> PositionReader<R> {
+        return PositionReader {
+            inner,
+            position: Position::start(),
+        };
+    }
+}
+
+impl<R: BufRead> Read for PositionReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position.advance(&buf[..read]);
+        return Ok(read);
+    }
+}
+
+impl<R: BufRead> BufRead for PositionReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        return self.inner.fill_buf();
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // the bytes being consumed are still at the front of the buffer
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.position.advance(&buf[..amt.min(buf.len())]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+fn read_prop<R: BufRead>(reader: &mut Reader<R>, start: &BytesStart) -> Result<Prop> {
+    return Ok(Prop {
+        prop_type: crate::get_attributes(reader, start)?
+            .get("type")
+            .cloned()
+            .unwrap_or_default(),
+        value: crate::read_element_text(reader, start)?,
+    });
+}
+
+/// Reads the `<header>` attributes; missing required attributes keep the
+/// values of [`TmxHeader::default`].
+fn parse_header(attributes: &HashMap<String, String>) -> TmxHeader {
+    let default = TmxHeader::default();
+    let get = |name: &str, default: String| attributes.get(name).cloned().unwrap_or(default);
+
+    return TmxHeader {
+        creation_tool: get("creationtool", default.creation_tool),
+        creation_tool_version: get("creationtoolversion", default.creation_tool_version),
+        seg_type: get("segtype", default.seg_type),
+        o_tmf: get("o-tmf", default.o_tmf),
+        admin_lang: get("adminlang", default.admin_lang),
+        src_lang: get("srclang", default.src_lang),
+        data_type: get("datatype", default.data_type),
+        o_encoding: attributes.get("o-encoding").cloned(),
+        attributes: TmxAttributes::from_attributes(attributes),
+        props: Vec::new(),
+        notes: Vec::new(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TmxReader;
+    use crate::tmx::{search_in_tu, TmxWriter};
+    use std::collections::HashMap;
+
+    #[test]
+    fn streams_units_for_search_stats_and_conversion() {
+        let tmx_reader = TmxReader::from_path("./tests/CITIC.tmx").unwrap();
+        assert_eq!(tmx_reader.header().creation_tool, "SDL Language Platform");
+
+        let mut tmx_writer = TmxWriter::new(Vec::new(), &tmx_reader.header().to_owned()).unwrap();
+        let mut languages: HashMap<String, usize> = HashMap::new();
+        let mut match_results = Vec::new();
+        let options = crate::SearchOptions::default();
+        let from = crate::SearchSource {
+            path: "CITIC.tmx",
+            format: crate::FileFormat::Tmx,
+            file_index: 0,
+            source_language: &tmx_reader.header().src_lang.to_owned(),
+            target_language: "",
+        };
+
+        for (unit_index, tu) in tmx_reader.enumerate() {
+            let tu = tu.unwrap();
+            for tuv in &tu.tuvs {
+                *languages.entry(tuv.language.to_owned()).or_default() += 1;
+            }
+            search_in_tu(
+                &from,
+                unit_index,
+                &tu,
+                &options,
+                &"账户".to_string(),
+                &mut match_results,
+            );
+            tmx_writer.write_tu(&tu).unwrap();
+        }
+
+        assert_eq!(languages.get("zh-cn"), Some(&3));
+        assert_eq!(match_results.len(), 2);
+        // "银行为客户开立了一个新账户。"
+        assert_eq!(match_results[0].occurrences[0].span, 33..39);
+        assert_eq!(match_results[0].occurrences[0].char_span, 11..13);
+
+        let converted = tmx_writer.finish().unwrap();
+        let reread = TmxReader::new(converted.as_slice())
+            .unwrap()
+            .collect::<crate::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(reread.len(), 3);
+        assert_eq!(reread[0].attributes.usage_count, Some(3));
+    }
+
+    #[test]
+    fn errors_carry_the_line() {
+        let broken = "<tmx version=\"1.4\"><body>\n<tu><tuv xml:lang=\"en\"><seg>a</tuv></tu>\n</body></tmx>";
+        let path = std::env::temp_dir().join("translation-xml-parser-broken.tmx");
+        std::fs::write(&path, broken).unwrap();
+
+        let mut tmx_reader = TmxReader::from_path(path.to_str().unwrap()).unwrap();
+        match tmx_reader.next() {
+            Some(Err(crate::Error::Xml { position, .. })) => assert_eq!(position.line, 2),
+            other => panic!(
+                "expected an XML error, got {:?}",
+                other.map(|r| r.map(|_| ()))
+            ),
+        }
+        assert!(tmx_reader.next().is_none());
+    }
+}
@@ -0,0 +1,442 @@
+use super::{Prop, TmxAttributes, TmxFile, TmxHeader, TU};
+use crate::{attributes_to_xml, Error, Result, SegNode};
+use quick_xml::escape::{escape, partial_escape};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Writes TMX 1.4b one `<tu>` at a time, so large memories can be exported
+/// without holding them in memory.
+pub struct TmxWriter<W: Write> {
+    inner: W,
+    path: String,
+}
+
+impl TmxWriter<BufWriter<File>> {
+    pub fn create(path: &str, header: &TmxHeader) -> Result<TmxWriter<BufWriter<File>>> {
+        let file = File::create(path).map_err(|e| Error::Write {
+            path: path.to_owned(),
+            source: e,
+        })?;
+
+        let mut tmx_writer = TmxWriter::new(BufWriter::new(file), header)?;
+        tmx_writer.path = path.to_owned();
+        return Ok(tmx_writer);
+    }
+}
+
+impl<W: Write> TmxWriter<W> {
+    pub fn new(inner: W, header: &TmxHeader) -> Result<TmxWriter<W>> {
+        let mut tmx_writer = TmxWriter {
+            inner,
+            path: String::new(),
+        };
+
+        let mut s = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        s.push_str("<tmx version=\"1.4\">\n");
+        s.push_str(&format!(
+            "  <header creationtool=\"{}\" creationtoolversion=\"{}\" segtype=\"{}\" o-tmf=\"{}\" adminlang=\"{}\" srclang=\"{}\" datatype=\"{}\"",
+            escape(&header.creation_tool),
+            escape(&header.creation_tool_version),
+            escape(&header.seg_type),
+            escape(&header.o_tmf),
+            escape(&header.admin_lang),
+            escape(&header.src_lang),
+            escape(&header.data_type),
+        ));
+        if let Some(o_encoding) = &header.o_encoding {
+            s.push_str(&format!(" o-encoding=\"{}\"", escape(o_encoding)));
+        }
+        s.push_str(&tmx_attributes_to_xml(&header.attributes));
+        if header.props.is_empty() && header.notes.is_empty() {
+            s.push_str("/>\n");
+        } else {
+            s.push_str(">\n");
+            write_notes_and_props(&mut s, "    ", &header.notes, &header.props);
+            s.push_str("  </header>\n");
+        }
+        s.push_str("  <body>\n");
+
+        tmx_writer.write_str(&s)?;
+        return Ok(tmx_writer);
+    }
+
+    pub fn write_tu(&mut self, tu: &TU) -> Result<()> {
+        let mut s = String::from("    <tu");
+        if !tu.tuid.is_empty() {
+            s.push_str(&format!(" tuid=\"{}\"", escape(&tu.tuid)));
+        }
+        if let Some(src_lang) = &tu.src_lang {
+            s.push_str(&format!(" srclang=\"{}\"", escape(src_lang)));
+        }
+        s.push_str(&tmx_attributes_to_xml(&tu.attributes));
+        s.push_str(">\n");
+        write_notes_and_props(&mut s, "      ", &tu.notes, &tu.props);
+
+        for tuv in &tu.tuvs {
+            s.push_str(&format!(
+                "      <tuv xml:lang=\"{}\"{}>\n",
+                escape(&tuv.language),
+                tmx_attributes_to_xml(&tuv.attributes)
+            ));
+            write_notes_and_props(&mut s, "        ", &tuv.notes, &tuv.props);
+            s.push_str(&format!("        <seg>{}</seg>\n", segs_to_tmx(&tuv.seg)));
+            s.push_str("      </tuv>\n");
+        }
+        s.push_str("    </tu>\n");
+
+        return self.write_str(&s);
+    }
+
+    /// Closes the document and hands back the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.write_str("  </body>\n</tmx>\n")?;
+        self.inner.flush().map_err(|e| Error::Write {
+            path: self.path.to_owned(),
+            source: e,
+        })?;
+        return Ok(self.inner);
+    }
+
+    fn write_str(&mut self, s: &str) -> Result<()> {
+        return self
+            .inner
+            .write_all(s.as_bytes())
+            .map_err(|e| Error::Write {
+                path: self.path.to_owned(),
+                source: e,
+            });
+    }
+}
+
+fn tmx_attributes_to_xml(attributes: &TmxAttributes) -> String {
+    let mut s = String::new();
+    let usage_count = attributes.usage_count.map(|c| c.to_string());
+
+    for (name, value) in [
+        ("creationdate", &attributes.creation_date),
+        ("creationid", &attributes.creation_id),
+        ("changedate", &attributes.change_date),
+        ("changeid", &attributes.change_id),
+        ("usagecount", &usage_count),
+        ("lastusagedate", &attributes.last_usage_date),
+    ] {
+        if let Some(value) = value {
+            s.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+        }
+    }
+
+    return s;
+}
+
+fn write_notes_and_props(s: &mut String, indent: &str, notes: &[String], props: &[Prop]) {
+    for note in notes {
+        s.push_str(&format!(
+            "{}<note>{}</note>\n",
+            indent,
+            partial_escape(note)
+        ));
+    }
+    for prop in props {
+        s.push_str(&format!(
+            "{}<prop type=\"{}\">{}</prop>\n",
+            indent,
+            escape(&prop.prop_type),
+            partial_escape(&prop.value)
+        ));
+    }
+}
+
+/// The `i` attributes pairing `bpt` and `ept` within one segment.
+struct PairNumbers {
+    next: usize,
+    /// Numbers given to XLIFF pairs, by their `rid` (or `id`).
+    by_xliff_id: HashMap<String, usize>,
+}
+
+impl PairNumbers {
+    /// Starts numbering above the `i` values already in `segs`.
+    fn new(segs: &[Box<SegNode>]) -> PairNumbers {
+        fn max_i(segs: &[Box<SegNode>]) -> usize {
+            let mut max = 0;
+            for n in segs {
+                if let SegNode::OpenOrCloseNode {
+                    node_type,
+                    attributes,
+                    content,
+                } = n.as_ref()
+                {
+                    if node_type == "bpt" || node_type == "ept" {
+                        let i = attributes.get("i").and_then(|i| i.parse().ok());
+                        max = max.max(i.unwrap_or(0));
+                    }
+                    max = max.max(max_i(content));
+                }
+            }
+            return max;
+        }
+
+        return PairNumbers {
+            next: max_i(segs) + 1,
+            by_xliff_id: HashMap::new(),
+        };
+    }
+
+    fn fresh(&mut self) -> usize {
+        let i = self.next;
+        self.next += 1;
+        return i;
+    }
+
+    fn for_xliff_id(&mut self, id: &str) -> usize {
+        if let Some(i) = self.by_xliff_id.get(id) {
+            return *i;
+        }
+        let i = self.fresh();
+        self.by_xliff_id.insert(id.to_owned(), i);
+        return i;
+    }
+}
+
+/// The TMX attributes of a native code element. TMX attributes are kept;
+/// XLIFF ones are mapped: `rid`/`id` pair up as `i`, a numeric `id` becomes
+/// `x`, `ctype` becomes `type` and `it` positions `open`/`close` become
+/// `begin`/`end`.
+fn tmx_code_attributes(
+    node_type: &str,
+    attributes: &HashMap<String, String>,
+    pairs: &mut PairNumbers,
+) -> HashMap<String, String> {
+    let mut mapped = HashMap::new();
+    let id = attributes.get("id");
+
+    if node_type == "bpt" || node_type == "ept" {
+        let i = match attributes.get("i") {
+            Some(i) => i.to_owned(),
+            None => match attributes.get("rid").or(id) {
+                Some(xliff_id) => pairs.for_xliff_id(xliff_id).to_string(),
+                None => pairs.fresh().to_string(),
+            },
+        };
+        mapped.insert("i".to_string(), i);
+    }
+    if node_type == "ept" {
+        return mapped;
+    }
+
+    if node_type == "it" {
+        let pos = match attributes.get("pos").map(|p| p.as_str()) {
+            Some("end") | Some("close") => "end",
+            _ => "begin",
+        };
+        mapped.insert("pos".to_string(), pos.to_string());
+    }
+    let numeric_id = id.filter(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()));
+    if let Some(x) = attributes.get("x").or(numeric_id) {
+        mapped.insert("x".to_string(), x.to_owned());
+    }
+    if node_type != "ut" {
+        if let Some(t) = attributes.get("type").or(attributes.get("ctype")) {
+            mapped.insert("type".to_string(), t.to_owned());
+        }
+    }
+    if node_type == "ph" {
+        if let Some(assoc) = attributes.get("assoc") {
+            mapped.insert("assoc".to_string(), assoc.to_owned());
+        }
+    }
+
+    return mapped;
+}
+
+/// Serializes inline content as TMX 1.4b. TMX elements are kept as they
+/// are, XLIFF native code elements get TMX attributes, and other XLIFF
+/// inline elements are encapsulated as native code in `bpt`/`ept`/`it`/`ph`
+/// so the result stays valid TMX.
+pub fn segs_to_tmx(segs: &[Box<SegNode>]) -> String {
+    let mut s = String::new();
+    let mut pairs = PairNumbers::new(segs);
+
+    for n in segs {
+        write_tmx_inline(n, &mut s, &mut pairs);
+    }
+
+    return s;
+}
+
+fn write_tmx_inline(node: &SegNode, out: &mut String, pairs: &mut PairNumbers) {
+    match node {
+        SegNode::Text(text) => out.push_str(&partial_escape(text)),
+        SegNode::OpenOrCloseNode {
+            node_type,
+            attributes,
+            content,
+        } => match node_type.as_str() {
+            "bpt" | "ept" | "it" | "ph" | "ut" => {
+                let mapped = tmx_code_attributes(node_type, attributes, pairs);
+                out.push_str(&format!(
+                    "<{}{}>{}</{}>",
+                    node_type,
+                    attributes_to_xml(&mapped),
+                    crate::segs_to_xml(content),
+                    node_type
+                ));
+            }
+            "hi" => {
+                out.push_str(&format!("<hi{}>", attributes_to_xml(attributes)));
+                for n in content {
+                    write_tmx_inline(n, out, pairs);
+                }
+                out.push_str("</hi>");
+            }
+            "mrk" => {
+                for n in content {
+                    write_tmx_inline(n, out, pairs);
+                }
+            }
+            _ => {
+                let i = pairs.fresh();
+
+                let start_tag = format!("<{}{}>", node_type, attributes_to_xml(attributes));
+                out.push_str(&format!(
+                    "<bpt i=\"{}\">{}</bpt>",
+                    i,
+                    partial_escape(&start_tag)
+                ));
+                for n in content {
+                    write_tmx_inline(n, out, pairs);
+                }
+                out.push_str(&format!(
+                    "<ept i=\"{}\">{}</ept>",
+                    i,
+                    partial_escape(&format!("</{}>", node_type))
+                ));
+            }
+        },
+        SegNode::SelfClosingNode {
+            node_type,
+            attributes,
+        } => {
+            let native = partial_escape(&format!(
+                "<{}{}/>",
+                node_type,
+                attributes_to_xml(attributes)
+            ))
+            .into_owned();
+            match node_type.as_str() {
+                "bx" | "sc" => out.push_str(&format!("<it pos=\"begin\">{}</it>", native)),
+                "ex" | "ec" => out.push_str(&format!("<it pos=\"end\">{}</it>", native)),
+                "sm" | "em" => (),
+                _ => out.push_str(&format!("<ph>{}</ph>", native)),
+            }
+        }
+    }
+}
+
+impl TmxFile {
+    pub fn write<W: Write>(&self, writer: W) -> Result<W> {
+        let mut tmx_writer = TmxWriter::new(writer, &self.header)?;
+        for tu in &self.tus {
+            tmx_writer.write_tu(tu)?;
+        }
+        return tmx_writer.finish();
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut tmx_writer = TmxWriter::create(path, &self.header)?;
+        for tu in &self.tus {
+            tmx_writer.write_tu(tu)?;
+        }
+        tmx_writer.finish()?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tmx::{Prop, TmxFile, TmxHeader, TU, TUV};
+    use crate::SegNode;
+    use std::collections::HashMap;
+
+    #[test]
+    fn writes_tmx_from_scratch() {
+        let mut tu = TU::new("42").with_tuv(TUV::from_text("en-US", "Fish & <chips>"));
+        tu.props.push(Prop::new("x-Client", "ACME"));
+        tu.notes.push("Approved".to_string());
+        tu.tuvs.push(TUV::new(
+            "de-DE",
+            vec![
+                Box::new(SegNode::OpenOrCloseNode {
+                    node_type: "g".to_string(),
+                    attributes: HashMap::from([("id".to_string(), "1".to_string())]),
+                    content: vec![Box::new(SegNode::Text("Fisch".to_string()))],
+                }),
+                Box::new(SegNode::Text(" & Pommes".to_string())),
+            ],
+        ));
+
+        let header = TmxHeader {
+            src_lang: "en-us".to_string(),
+            ..Default::default()
+        };
+        let tmx = TmxFile::from_tus(header, vec![tu]);
+        let written = String::from_utf8(tmx.write(Vec::new()).unwrap()).unwrap();
+
+        assert!(written.contains("srclang=\"en-us\""));
+        assert!(written.contains("<prop type=\"x-Client\">ACME</prop>"));
+        assert!(written.contains("<seg>Fish &amp; &lt;chips&gt;</seg>"));
+        assert!(written.contains(
+            "<seg><bpt i=\"1\">&lt;g id=\"1\"&gt;</bpt>Fisch<ept i=\"1\">&lt;/g&gt;</ept> &amp; Pommes</seg>"
+        ));
+
+        let out = std::env::temp_dir().join("translation-xml-parser-writer.tmx");
+        tmx.save(out.to_str().unwrap()).unwrap();
+        let reread = TmxFile::new(out.to_str().unwrap()).unwrap();
+        assert_eq!(reread.tus.len(), 1);
+        assert_eq!(
+            crate::extract_text(&reread.tus[0].tuvs[0].seg, false),
+            "Fish & <chips>"
+        );
+        assert_eq!(
+            crate::extract_text(&reread.tus[0].tuvs[1].seg, false),
+            "Fisch & Pommes"
+        );
+    }
+
+    #[test]
+    fn maps_xliff_codes_to_tmx() {
+        let code = |node_type: &str, attributes: &[(&str, &str)], native: &str| {
+            Box::new(SegNode::OpenOrCloseNode {
+                node_type: node_type.to_string(),
+                attributes: attributes
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                content: vec![Box::new(SegNode::Text(native.to_string()))],
+            })
+        };
+        let text = |t: &str| Box::new(SegNode::Text(t.to_string()));
+        let seg = vec![
+            code("bpt", &[("i", "1"), ("x", "1")], "<b>"),
+            text("TMX"),
+            code("ept", &[("i", "1")], "</b>"),
+            code(
+                "bpt",
+                &[("id", "5"), ("rid", "a"), ("ctype", "bold")],
+                "<b>",
+            ),
+            text("XLIFF"),
+            code("ept", &[("id", "6"), ("rid", "a")], "</b>"),
+            code("ph", &[("id", "7"), ("ctype", "image")], "<img/>"),
+            code("it", &[("id", "8"), ("pos", "close")], "</i>"),
+        ];
+
+        assert_eq!(
+            super::segs_to_tmx(&seg),
+            "<bpt i=\"1\" x=\"1\">&lt;b&gt;</bpt>TMX<ept i=\"1\">&lt;/b&gt;</ept>\
+             <bpt i=\"2\" type=\"bold\" x=\"5\">&lt;b&gt;</bpt>XLIFF<ept i=\"2\">&lt;/b&gt;</ept>\
+             <ph type=\"image\" x=\"7\">&lt;img/&gt;</ph>\
+             <it pos=\"end\" x=\"8\">&lt;/i&gt;</it>"
+        );
+    }
+}
@@ -1,20 +1,31 @@
+use crate::bilingual::search_bilingual_in_transunits;
 use crate::{
-    search_in_transunits, GetMeta, MatchResult, MetaInfo, SearchInFile, SearchString, SegNode,
+    search_in_transunits, BilingualMatch, BilingualQuery, Error, FileFormat, GetMeta, MatchResult,
+    MetaInfo, Position, Result, SearchInFile, SearchOptions, SearchSource, SearchString, SegNode,
 };
 use quick_xml::events::Event;
 use quick_xml::reader::Reader;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use std::fs::File;
 use std::io::prelude::*;
-use std::process::exit;
-use zip;
+use zip::ZipArchive;
+
+mod writer;
+
+use writer::TargetSpan;
 
 #[derive(Debug, Clone)]
 pub struct XliffFile {
     pub path: String,
+    pub format: FileFormat,
+    pub version: String,
     pub xfiles: Vec<XFile>,
-    raw_content: String,
+    /// Hash of the parsed document. The writer reads the original again and
+    /// checks it against this instead of keeping a copy in memory.
+    original_hash: u64,
+    entry: Option<String>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -27,141 +38,893 @@ pub struct XFile {
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct TransUnit {
     pub id: String,
-    pub sn: u16,
+    /// `id` of the 2.x `<segment>`; `id` is the one of its `<unit>`.
+    pub segment_id: Option<String>,
+    pub sn: u32,
     pub translate: String,
+    pub source: Vec<Box<SegNode>>,
+    /// `None` when the unit has no `<target>` element at all.
+    pub target: Option<Vec<Box<SegNode>>>,
+    /// `state` of `<target>` in 1.2, of `<segment>` in 2.x.
+    pub state: Option<TargetState>,
+    /// `state-qualifier` of `<target>` in 1.2, `subState` of `<segment>` in
+    /// 2.x.
+    pub state_qualifier: Option<String>,
+    pub approved: Option<bool>,
+    pub notes: Vec<Note>,
+    /// `resname` in 1.2, `name` of `<unit>` in 2.x.
+    pub resname: Option<String>,
+    /// `restype` in 1.2, `type` of `<unit>` in 2.x.
+    pub restype: Option<String>,
+    pub maxwidth: Option<u32>,
+    pub size_unit: Option<String>,
+    /// SDL Trados segment definitions from `<sdl:seg-defs>`.
+    pub sdl_segs: Vec<SdlSegment>,
+    /// `<seg-source>`: the source with its `<mrk mtype="seg">` segmentation.
+    pub seg_source: Option<Vec<Box<SegNode>>>,
+    /// memoQ `mq:` attributes and comments, for `.mqxliff` documents.
+    pub memoq: Option<MemoqInfo>,
+    pub alt_trans: Vec<AltTrans>,
+    /// Where the `<source>` and `<target>` content starts in the document.
+    pub source_position: Position,
+    pub target_position: Position,
+    #[serde(skip)]
+    pub(crate) span: TargetSpan,
+}
+
+/// A TM or MT candidate from an `<alt-trans>` element.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct AltTrans {
     pub source: Vec<Box<SegNode>>,
     pub target: Vec<Box<SegNode>>,
+    pub match_quality: Option<String>,
+    pub origin: Option<String>,
+    pub tool_id: Option<String>,
+    /// `xml:lang` of the candidate's `<source>` and `<target>`, when they
+    /// differ from the file languages.
+    pub source_language: Option<String>,
+    pub target_language: Option<String>,
+    /// Where the candidate's `<source>` and `<target>` content starts.
+    pub source_position: Position,
+    pub target_position: Position,
 }
 
-impl XliffFile {
-    pub fn new(path: &str) -> XliffFile {
-        let content = crate::read_to_string(path);
+/// The `state` of a target. XLIFF 1.2 and 2.x values are kept apart so a
+/// written document uses the vocabulary it was read with.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TargetState {
+    New,
+    NeedsTranslation,
+    NeedsAdaptation,
+    NeedsL10n,
+    NeedsReviewTranslation,
+    NeedsReviewAdaptation,
+    NeedsReviewL10n,
+    Translated,
+    SignedOff,
+    Final,
+    /// 2.x only.
+    Initial,
+    /// 2.x only.
+    Reviewed,
+    /// Custom `x-` values and anything else not in the specification.
+    Other(String),
+}
 
-        let mut xliff_file = XliffFile {
-            path: path.to_owned(),
-            xfiles: Vec::new(),
-            raw_content: content,
+impl TargetState {
+    pub fn as_str(&self) -> &str {
+        match self {
+            TargetState::New => "new",
+            TargetState::NeedsTranslation => "needs-translation",
+            TargetState::NeedsAdaptation => "needs-adaptation",
+            TargetState::NeedsL10n => "needs-l10n",
+            TargetState::NeedsReviewTranslation => "needs-review-translation",
+            TargetState::NeedsReviewAdaptation => "needs-review-adaptation",
+            TargetState::NeedsReviewL10n => "needs-review-l10n",
+            TargetState::Translated => "translated",
+            TargetState::SignedOff => "signed-off",
+            TargetState::Final => "final",
+            TargetState::Initial => "initial",
+            TargetState::Reviewed => "reviewed",
+            TargetState::Other(s) => s,
+        }
+    }
+}
+
+impl From<&str> for TargetState {
+    fn from(s: &str) -> TargetState {
+        match s {
+            "new" => TargetState::New,
+            "needs-translation" => TargetState::NeedsTranslation,
+            "needs-adaptation" => TargetState::NeedsAdaptation,
+            "needs-l10n" => TargetState::NeedsL10n,
+            "needs-review-translation" => TargetState::NeedsReviewTranslation,
+            "needs-review-adaptation" => TargetState::NeedsReviewAdaptation,
+            "needs-review-l10n" => TargetState::NeedsReviewL10n,
+            "translated" => TargetState::Translated,
+            "signed-off" => TargetState::SignedOff,
+            "final" => TargetState::Final,
+            "initial" => TargetState::Initial,
+            "reviewed" => TargetState::Reviewed,
+            _ => TargetState::Other(s.to_owned()),
+        }
+    }
+}
+
+/// A `<note>` of a unit. In 2.x the notes of a `<unit>` are copied to each
+/// of its segments.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Note {
+    pub text: String,
+    pub from: Option<String>,
+    /// 1 (highest) to 10.
+    pub priority: Option<u8>,
+    /// `annotates` in 1.2, `appliesTo` in 2.x: `source`, `target` or
+    /// `general`.
+    pub annotates: Option<String>,
+    /// 2.x only.
+    pub category: Option<String>,
+}
+
+/// The `conf` attribute of an SDL Trados `<sdl:seg>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SdlConfirmation {
+    NotTranslated,
+    Draft,
+    Translated,
+    RejectedTranslation,
+    ApprovedTranslation,
+    RejectedSignOff,
+    ApprovedSignOff,
+    Other(String),
+}
+
+impl SdlConfirmation {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SdlConfirmation::NotTranslated => "NotTranslated",
+            SdlConfirmation::Draft => "Draft",
+            SdlConfirmation::Translated => "Translated",
+            SdlConfirmation::RejectedTranslation => "RejectedTranslation",
+            SdlConfirmation::ApprovedTranslation => "ApprovedTranslation",
+            SdlConfirmation::RejectedSignOff => "RejectedSignOff",
+            SdlConfirmation::ApprovedSignOff => "ApprovedSignOff",
+            SdlConfirmation::Other(s) => s,
+        }
+    }
+}
+
+impl From<&str> for SdlConfirmation {
+    fn from(s: &str) -> SdlConfirmation {
+        match s {
+            "NotTranslated" => SdlConfirmation::NotTranslated,
+            "Draft" => SdlConfirmation::Draft,
+            "Translated" => SdlConfirmation::Translated,
+            "RejectedTranslation" => SdlConfirmation::RejectedTranslation,
+            "ApprovedTranslation" => SdlConfirmation::ApprovedTranslation,
+            "RejectedSignOff" => SdlConfirmation::RejectedSignOff,
+            "ApprovedSignOff" => SdlConfirmation::ApprovedSignOff,
+            _ => SdlConfirmation::Other(s.to_owned()),
+        }
+    }
+}
+
+/// One `<sdl:seg>` of `<sdl:seg-defs>`. `id` matches the `mid` of the
+/// `<mrk mtype="seg">` it describes.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SdlSegment {
+    pub id: String,
+    pub conf: Option<SdlConfirmation>,
+    pub locked: bool,
+    pub percent: Option<u8>,
+    pub origin: Option<String>,
+    pub origin_system: Option<String>,
+}
+
+impl SdlSegment {
+    fn from_attributes(attributes: &HashMap<String, String>) -> SdlSegment {
+        return SdlSegment {
+            id: attributes.get("id").cloned().unwrap_or_default(),
+            conf: attributes
+                .get("conf")
+                .map(|c| SdlConfirmation::from(c.as_str())),
+            locked: attributes.get("locked").is_some_and(|l| l == "true"),
+            percent: attributes.get("percent").and_then(|p| p.parse().ok()),
+            origin: attributes.get("origin").cloned(),
+            origin_system: attributes.get("origin-system").cloned(),
         };
-        xliff_file.parse();
-        return xliff_file;
     }
+}
+
+/// The `mq:status` of a memoQ segment.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MemoqStatus {
+    NotStarted,
+    Editing,
+    PartiallyEdited,
+    ManuallyConfirmed,
+    Reviewer1Confirmed,
+    Reviewer2Confirmed,
+    Rejected,
+    Pretranslated,
+    MachineTranslated,
+    AssembledFromFragments,
+    Other(String),
+}
+
+impl MemoqStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            MemoqStatus::NotStarted => "NotStarted",
+            MemoqStatus::Editing => "Editing",
+            MemoqStatus::PartiallyEdited => "PartiallyEdited",
+            MemoqStatus::ManuallyConfirmed => "ManuallyConfirmed",
+            MemoqStatus::Reviewer1Confirmed => "Reviewer1Confirmed",
+            MemoqStatus::Reviewer2Confirmed => "Reviewer2Confirmed",
+            MemoqStatus::Rejected => "Rejected",
+            MemoqStatus::Pretranslated => "Pretranslated",
+            MemoqStatus::MachineTranslated => "MachineTranslated",
+            MemoqStatus::AssembledFromFragments => "AssembledFromFragments",
+            MemoqStatus::Other(s) => s,
+        }
+    }
+}
+
+impl From<&str> for MemoqStatus {
+    fn from(s: &str) -> MemoqStatus {
+        match s {
+            "NotStarted" => MemoqStatus::NotStarted,
+            "Editing" => MemoqStatus::Editing,
+            "PartiallyEdited" => MemoqStatus::PartiallyEdited,
+            "ManuallyConfirmed" => MemoqStatus::ManuallyConfirmed,
+            "Reviewer1Confirmed" => MemoqStatus::Reviewer1Confirmed,
+            "Reviewer2Confirmed" => MemoqStatus::Reviewer2Confirmed,
+            "Rejected" => MemoqStatus::Rejected,
+            "Pretranslated" => MemoqStatus::Pretranslated,
+            "MachineTranslated" => MemoqStatus::MachineTranslated,
+            "AssembledFromFragments" => MemoqStatus::AssembledFromFragments,
+            _ => MemoqStatus::Other(s.to_owned()),
+        }
+    }
+}
+
+/// One `<mq:comment>` of `<mq:comments>`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MemoqComment {
+    pub text: String,
+    pub creator_user: Option<String>,
+    pub time: Option<String>,
+    /// `Source` or `Target`.
+    pub applies_to: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MemoqInfo {
+    pub status: Option<MemoqStatus>,
+    pub percent: Option<u8>,
+    pub locked: bool,
+    pub last_changed_timestamp: Option<String>,
+    pub last_changing_user: Option<String>,
+    pub comments: Vec<MemoqComment>,
+}
+
+impl MemoqInfo {
+    /// `None` when the unit has no `mq:` attributes.
+    fn from_attributes(attributes: &HashMap<String, String>) -> Option<MemoqInfo> {
+        if !attributes.keys().any(|k| k.starts_with("mq:")) {
+            return None;
+        }
+
+        return Some(MemoqInfo {
+            status: attributes
+                .get("mq:status")
+                .map(|s| MemoqStatus::from(s.as_str())),
+            percent: attributes.get("mq:percent").and_then(|p| p.parse().ok()),
+            locked: attributes
+                .get("mq:locked")
+                .is_some_and(|l| l == "locked" || l == "true"),
+            last_changed_timestamp: attributes.get("mq:lastchangedtimestamp").cloned(),
+            last_changing_user: attributes.get("mq:lastchanginguser").cloned(),
+            comments: Vec::new(),
+        });
+    }
+}
+
+/// One `<mrk mtype="seg" mid="...">` segment of a paragraph unit, linked to
+/// the `<sdl:seg>` with the same id. A view into the unit; see
+/// [`TransUnit::segments`].
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct MrkSegment<'a> {
+    pub mid: &'a str,
+    pub source: &'a [Box<SegNode>],
+    pub target: Option<&'a [Box<SegNode>]>,
+    pub sdl: Option<&'a SdlSegment>,
+}
 
-    pub fn new_zipped(path: &str, inner_xliff_name: &str) -> XliffFile {
-        let zipped_file = match std::fs::File::open(&path) {
-            Ok(file) => file,
-            Err(e) => {
-                eprintln!("Failed to open {}: {:?}", path, e);
-                exit(1);
+/// Collects the `<mrk mtype="seg">` elements of `segs` with their content,
+/// looking inside other inline elements such as `<g>` as well.
+fn mrk_segments<'a>(segs: &'a [Box<SegNode>], found: &mut Vec<(&'a str, &'a [Box<SegNode>])>) {
+    for n in segs {
+        if let SegNode::OpenOrCloseNode { content, .. } = n.as_ref() {
+            if let Some(mid) = segment_mid(n) {
+                found.push((mid, content));
+            } else {
+                mrk_segments(content, found);
             }
-        };
+        }
+    }
+}
 
-        let mut archive = match zip::ZipArchive::new(zipped_file) {
-            Ok(zipped) => zipped,
-            Err(_e) => {
-                eprintln!("Invalid zip file {}", path);
-                exit(2);
+/// The content of the `<mrk mtype="seg">` with `mid` in `segs`.
+#[allow(clippy::vec_box)]
+fn mrk_content_mut<'a>(
+    segs: &'a mut [Box<SegNode>],
+    mid: &str,
+) -> Option<&'a mut Vec<Box<SegNode>>> {
+    for n in segs {
+        let is_segment = segment_mid(n) == Some(mid);
+        if let SegNode::OpenOrCloseNode { content, .. } = n.as_mut() {
+            if is_segment {
+                return Some(content);
+            }
+            if let Some(found) = mrk_content_mut(content, mid) {
+                return Some(found);
             }
+        }
+    }
+
+    return None;
+}
+
+/// Replaces the content of every `<mrk mtype="seg">` in `segs` by `fill`.
+fn fill_segments(segs: &mut [Box<SegNode>], fill: &mut dyn FnMut(&str) -> Vec<Box<SegNode>>) {
+    for n in segs {
+        let mid = segment_mid(n).map(str::to_owned);
+        if let SegNode::OpenOrCloseNode { content, .. } = n.as_mut() {
+            match mid {
+                Some(mid) => *content = fill(&mid),
+                None => fill_segments(content, fill),
+            }
+        }
+    }
+}
+
+/// The `mid` of a `<mrk mtype="seg">` element.
+pub(crate) fn segment_mid(node: &SegNode) -> Option<&str> {
+    match node {
+        SegNode::OpenOrCloseNode {
+            node_type,
+            attributes,
+            ..
+        } if node_type == "mrk" && attributes.get("mtype").map(|m| m.as_str()) == Some("seg") => {
+            return Some(
+                attributes
+                    .get("mid")
+                    .map(|m| m.as_str())
+                    .unwrap_or_default(),
+            );
+        }
+        _ => return None,
+    }
+}
+
+/// Translation status used for counting and filtering units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum UnitStatus {
+    Translated,
+    Untranslated,
+    /// `translate="no"`, or every SDL segment locked.
+    Locked,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StatusCounts {
+    pub translated: usize,
+    pub untranslated: usize,
+    pub locked: usize,
+}
+
+impl StatusCounts {
+    pub fn from_units<'a>(units: impl IntoIterator<Item = &'a TransUnit>) -> StatusCounts {
+        let mut counts = StatusCounts::default();
+        for status in units.into_iter().flat_map(|u| u.statuses()) {
+            match status {
+                UnitStatus::Translated => counts.translated += 1,
+                UnitStatus::Untranslated => counts.untranslated += 1,
+                UnitStatus::Locked => counts.locked += 1,
+            }
+        }
+        return counts;
+    }
+
+    pub fn total(&self) -> usize {
+        return self.translated + self.untranslated + self.locked;
+    }
+}
+
+impl TransUnit {
+    pub fn status(&self) -> UnitStatus {
+        if self.translate == "no" || self.is_locked() {
+            return UnitStatus::Locked;
+        }
+
+        return match &self.target {
+            Some(target) if !crate::extract_text(target, false).trim().is_empty() => {
+                UnitStatus::Translated
+            }
+            _ => UnitStatus::Untranslated,
         };
+    }
+
+    pub fn segment_status(&self, segment: &MrkSegment) -> UnitStatus {
+        if self.translate == "no" || segment.sdl.is_some_and(|s| s.locked) {
+            return UnitStatus::Locked;
+        }
 
-        let mut file = match archive.by_name(inner_xliff_name) {
-            Ok(file) => file,
-            Err(_) => {
-                eprintln!("{} not found in zipped file", inner_xliff_name);
-                exit(3);
+        return match segment.target {
+            Some(target) if !crate::extract_text(target, false).trim().is_empty() => {
+                UnitStatus::Translated
             }
+            _ => UnitStatus::Untranslated,
         };
+    }
+
+    /// One status per real segment: per `mid` for segmented units, otherwise
+    /// the status of the whole unit.
+    pub fn statuses(&self) -> Vec<UnitStatus> {
+        let segments = self.segments();
+        if segments.is_empty() {
+            return vec![self.status()];
+        }
+
+        return segments.iter().map(|s| self.segment_status(s)).collect();
+    }
 
-        let mut contents = String::new();
+    /// The `<mrk mtype="seg">` segments of `seg_source` and `target`, paired
+    /// by `mid`; empty when the unit is not segmented. `source` and `target`
+    /// still hold the whole paragraph.
+    pub fn segments(&self) -> Vec<MrkSegment<'_>> {
+        let mut sources = Vec::new();
+        mrk_segments(self.seg_source.as_deref().unwrap_or_default(), &mut sources);
 
-        match file.read_to_string(&mut contents) {
-            Ok(..) => (),
-            Err(..) => {
-                eprintln!("Failed to read {} into a string", path);
-                exit(4);
+        let mut targets = Vec::new();
+        mrk_segments(self.target.as_deref().unwrap_or_default(), &mut targets);
+
+        return sources
+            .into_iter()
+            .map(|(mid, source)| MrkSegment {
+                mid,
+                source,
+                target: targets
+                    .iter()
+                    .find(|(target_mid, _)| *target_mid == mid)
+                    .map(|(_, target)| *target),
+                sdl: self.sdl_segs.iter().find(|s| s.id == mid),
+            })
+            .collect();
+    }
+
+    /// Sets the target of segment `mid`. A target without that segment is
+    /// rebuilt from `seg_source`, keeping the targets of the other segments
+    /// and leaving the untranslated ones empty. Returns false when the unit
+    /// has no segment `mid`.
+    pub fn set_segment_target(&mut self, mid: &str, target: Vec<Box<SegNode>>) -> bool {
+        if let Some(content) = self
+            .target
+            .as_deref_mut()
+            .and_then(|t| mrk_content_mut(t, mid))
+        {
+            *content = target;
+            return true;
+        }
+
+        let mut rebuilt = match &self.seg_source {
+            Some(seg_source) => seg_source.to_owned(),
+            None => return false,
+        };
+        if mrk_content_mut(&mut rebuilt, mid).is_none() {
+            return false;
+        }
+
+        let mut translated = Vec::new();
+        mrk_segments(self.target.as_deref().unwrap_or_default(), &mut translated);
+        let mut target = Some(target);
+        fill_segments(&mut rebuilt, &mut |segment_mid| {
+            if segment_mid == mid {
+                return target.take().unwrap_or_default();
             }
+            return translated
+                .iter()
+                .find(|(translated_mid, _)| *translated_mid == segment_mid)
+                .map(|(_, content)| content.to_vec())
+                .unwrap_or_default();
+        });
+        self.target = Some(rebuilt);
+
+        return true;
+    }
+
+    /// Approved by `approved="yes"`, a `final`/`signed-off` state, or every
+    /// SDL segment being approved.
+    pub fn is_approved(&self) -> bool {
+        if let Some(approved) = self.approved {
+            return approved;
+        }
+        if matches!(
+            self.state,
+            Some(TargetState::Final) | Some(TargetState::SignedOff)
+        ) {
+            return true;
+        }
+
+        return !self.sdl_segs.is_empty()
+            && self.sdl_segs.iter().all(|s| {
+                matches!(
+                    s.conf,
+                    Some(SdlConfirmation::ApprovedTranslation)
+                        | Some(SdlConfirmation::ApprovedSignOff)
+                )
+            });
+    }
+
+    pub fn is_locked(&self) -> bool {
+        if self.memoq.as_ref().is_some_and(|mq| mq.locked) {
+            return true;
         }
 
+        return !self.sdl_segs.is_empty() && self.sdl_segs.iter().all(|s| s.locked);
+    }
+}
+
+impl AltTrans {
+    /// Parses `match-quality` values such as `"85"`, `"85%"` or `"99.5"`.
+    pub fn match_percent(&self) -> Option<f32> {
+        return self
+            .match_quality
+            .as_deref()?
+            .trim()
+            .trim_end_matches('%')
+            .parse()
+            .ok();
+    }
+}
+
+impl XliffFile {
+    pub fn new(path: &str) -> Result<XliffFile> {
+        let content = crate::read_to_string(path)?;
+
+        return XliffFile::from_content(path, FileFormat::Xliff, content);
+    }
+
+    pub fn new_zipped(path: &str, inner_xliff_name: &str) -> Result<XliffFile> {
+        let mut archive = open_archive(path)?;
+        let contents = read_zip_entry(&mut archive, path, inner_xliff_name)?;
+
+        let format = if inner_xliff_name.ends_with(".mqxliff") {
+            FileFormat::Mqxlz
+        } else {
+            FileFormat::Xlz
+        };
+
+        let mut xliff_file = XliffFile::from_content(path, format, contents)?;
+        xliff_file.entry = Some(inner_xliff_name.to_owned());
+        return Ok(xliff_file);
+    }
+
+    /// Reads every `.sdlxliff` document of an SDL project package into one
+    /// `XliffFile`, keeping the `<file>` elements of all documents in order.
+    pub fn new_sdlppx(path: &str) -> Result<XliffFile> {
+        let mut archive = open_archive(path)?;
+
+        let mut names = archive
+            .file_names()
+            .filter(|n| n.ends_with(".sdlxliff"))
+            .map(|n| n.to_owned())
+            .collect::<Vec<String>>();
+        names.sort();
+
+        let mut version = String::new();
+        let mut xfiles = Vec::new();
+        for name in names {
+            let contents = read_zip_entry(&mut archive, path, &name)?;
+            let inner = XliffFile::from_content(path, FileFormat::Sdlppx, contents)?;
+            version = inner.version;
+            xfiles.extend(inner.xfiles);
+        }
+
+        return Ok(XliffFile {
+            path: path.to_owned(),
+            format: FileFormat::Sdlppx,
+            version,
+            xfiles,
+            original_hash: 0,
+            entry: None,
+        });
+    }
+
+    fn from_content(path: &str, format: FileFormat, content: String) -> Result<XliffFile> {
         let mut xliff_file = XliffFile {
             path: path.to_owned(),
+            format,
+            version: String::new(),
             xfiles: Vec::new(),
-            raw_content: contents,
+            original_hash: writer::hash_content(&content),
+            entry: None,
         };
-        xliff_file.parse();
-        return xliff_file;
+        xliff_file
+            .parse(&content)
+            .map_err(|e| e.locate(&content, path))?;
+        return Ok(xliff_file);
     }
 
-    fn parse(&mut self) {
+    /// Parses both XLIFF 1.2 and 2.x. A 2.x `<segment>` becomes one
+    /// `TransUnit` carrying the id of its `<unit>`; `<ignorable>` content is
+    /// not translatable and is skipped.
+    fn parse(&mut self, content: &str) -> Result<()> {
         let mut buf = Vec::new();
         let mut sn = 0;
 
+        let mut doc_src_language = String::new();
+        let mut doc_tgt_language = String::new();
+        // 2.x `<unit>` attributes and notes shared by its segments
+        let mut cur_unit = TransUnit::default();
+        let mut in_unit = false;
+
         let mut cur_xfile = XFile::default();
         let mut cur_trans_unit = TransUnit::default();
+        let mut cur_alt_trans: Option<AltTrans> = None;
         let mut cur_source: Vec<Box<SegNode>>;
-        let mut cur_target: Vec<Box<SegNode>>;
 
-        let mut reader = Reader::from_str(&self.raw_content);
+        let mut reader = Reader::from_str(content);
+        let mut position = Position::start();
 
         loop {
+            let event_start = reader.buffer_position();
             match reader.read_event_into(&mut buf) {
-                Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
+                Err(e) => return Err(Error::xml(reader.buffer_position(), e)),
                 Ok(Event::Start(e)) => match e.name().as_ref() {
+                    b"xliff" => {
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        self.version = attributes
+                            .get("version")
+                            .unwrap_or(&String::new())
+                            .to_owned();
+                        doc_src_language = attributes
+                            .get("srcLang")
+                            .unwrap_or(&String::new())
+                            .to_lowercase();
+                        doc_tgt_language = attributes
+                            .get("trgLang")
+                            .unwrap_or(&String::new())
+                            .to_lowercase();
+                    }
                     b"file" => {
-                        cur_xfile.src_language = crate::get_attributes(&reader, &e)
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        cur_xfile.src_language = attributes
                             .get("source-language")
-                            .unwrap_or(&String::new())
-                            .to_owned()
+                            .unwrap_or(&doc_src_language)
                             .to_lowercase();
-                        cur_xfile.tgt_language = crate::get_attributes(&reader, &e)
+                        cur_xfile.tgt_language = attributes
                             .get("target-language")
-                            .unwrap_or(&String::new())
-                            .to_owned()
+                            .unwrap_or(&doc_tgt_language)
                             .to_lowercase();
                     }
+                    b"unit" => {
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        cur_unit = TransUnit {
+                            id: attributes.get("id").unwrap_or(&String::new()).to_owned(),
+                            translate: attributes
+                                .get("translate")
+                                .unwrap_or(&"yes".to_string())
+                                .to_owned(),
+                            resname: attributes.get("name").cloned(),
+                            restype: attributes.get("type").cloned(),
+                            ..Default::default()
+                        };
+                        in_unit = true;
+                    }
+                    b"segment" => {
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        cur_trans_unit = cur_unit.clone();
+                        cur_trans_unit.segment_id = attributes.get("id").cloned();
+                        cur_trans_unit.state = attributes
+                            .get("state")
+                            .map(|v| TargetState::from(v.as_str()));
+                        cur_trans_unit.state_qualifier = attributes.get("subState").cloned();
+                        cur_trans_unit.span.state_tag = Some(event_start..reader.buffer_position());
+                        sn += 1;
+                        cur_trans_unit.sn = sn;
+                    }
                     b"trans-unit" => {
-                        cur_trans_unit.id = crate::get_attributes(&reader, &e)
-                            .get("id")
-                            .unwrap_or(&String::new())
-                            .to_owned();
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        cur_trans_unit.id =
+                            attributes.get("id").unwrap_or(&String::new()).to_owned();
                         sn += 1;
                         cur_trans_unit.sn = sn;
-                        cur_trans_unit.translate = crate::get_attributes(&reader, &e)
+                        cur_trans_unit.translate = attributes
                             .get("translate")
                             .unwrap_or(&"yes".to_string())
-                            .to_owned()
+                            .to_owned();
+                        cur_trans_unit.approved = attributes.get("approved").map(|v| v == "yes");
+                        cur_trans_unit.resname = attributes.get("resname").cloned();
+                        cur_trans_unit.restype = attributes.get("restype").cloned();
+                        cur_trans_unit.maxwidth =
+                            attributes.get("maxwidth").and_then(|v| v.parse().ok());
+                        cur_trans_unit.size_unit = attributes.get("size-unit").cloned();
+                        cur_trans_unit.memoq = MemoqInfo::from_attributes(&attributes);
+                        in_unit = true;
+                    }
+                    b"note" if in_unit && cur_alt_trans.is_none() => {
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        let note = Note {
+                            text: crate::read_element_text(&mut reader, &e)?,
+                            from: attributes.get("from").cloned(),
+                            priority: attributes.get("priority").and_then(|v| v.parse().ok()),
+                            annotates: attributes
+                                .get("annotates")
+                                .or_else(|| attributes.get("appliesTo"))
+                                .cloned(),
+                            category: attributes.get("category").cloned(),
+                        };
+                        if self.version.starts_with('2') {
+                            cur_unit.notes.push(note);
+                        } else {
+                            cur_trans_unit.notes.push(note);
+                        }
+                    }
+                    b"sdl:seg" => {
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        let sdl_seg = SdlSegment::from_attributes(&attributes);
+                        cur_trans_unit
+                            .span
+                            .record_sdl_seg(event_start..reader.buffer_position());
+                        cur_trans_unit.sdl_segs.push(sdl_seg);
+                    }
+                    b"mq:comment" => {
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        let comment = MemoqComment {
+                            text: crate::read_element_text(&mut reader, &e)?,
+                            creator_user: attributes.get("creatoruser").cloned(),
+                            time: attributes.get("time").cloned(),
+                            applies_to: attributes.get("appliesto").cloned(),
+                        };
+                        cur_trans_unit
+                            .memoq
+                            .get_or_insert_with(MemoqInfo::default)
+                            .comments
+                            .push(comment);
+                    }
+                    b"source" if cur_alt_trans.is_some() => {
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        position.advance_to(content, reader.buffer_position());
+                        if let Some(alt_trans) = cur_alt_trans.as_mut() {
+                            alt_trans.source_language =
+                                attributes.get("xml:lang").map(|l| l.to_lowercase());
+                            alt_trans.source_position = position;
+                            alt_trans.source = SegNode::parse_inline(&mut reader, &mut buf)?;
+                        }
+                    }
+                    b"target" if cur_alt_trans.is_some() => {
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        position.advance_to(content, reader.buffer_position());
+                        if let Some(alt_trans) = cur_alt_trans.as_mut() {
+                            alt_trans.target_language =
+                                attributes.get("xml:lang").map(|l| l.to_lowercase());
+                            alt_trans.target_position = position;
+                            alt_trans.target = SegNode::parse_inline(&mut reader, &mut buf)?;
+                        }
                     }
                     b"source" => {
-                        cur_source = SegNode::parse_inline(&mut reader, &mut buf);
-                        if cur_source.len() != 0 {
+                        position.advance_to(content, reader.buffer_position());
+                        cur_trans_unit.source_position = position;
+                        cur_source = SegNode::parse_inline(&mut reader, &mut buf)?;
+                        if !cur_source.is_empty() {
                             cur_trans_unit.source = cur_source;
                         }
+                        cur_trans_unit.span.record_source(
+                            content,
+                            event_start,
+                            reader.buffer_position(),
+                        );
+                    }
+                    b"seg-source" => {
+                        cur_trans_unit.seg_source =
+                            Some(SegNode::parse_inline(&mut reader, &mut buf)?);
+                        cur_trans_unit
+                            .span
+                            .record_seg_source(reader.buffer_position());
                     }
                     b"target" => {
-                        cur_target = SegNode::parse_inline(&mut reader, &mut buf);
-                        if cur_target.len() != 0 {
-                            cur_trans_unit.target = cur_target;
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        let tag = event_start..reader.buffer_position();
+                        position.advance_to(content, tag.end);
+                        cur_trans_unit.target_position = position;
+
+                        cur_trans_unit.target = Some(SegNode::parse_inline(&mut reader, &mut buf)?);
+
+                        let content_end = content[..reader.buffer_position()]
+                            .rfind("</")
+                            .unwrap_or(tag.end);
+                        cur_trans_unit.span.target_content = Some(tag.end..content_end);
+                        cur_trans_unit.span.target_tag = Some(tag.clone());
+                        if !self.version.starts_with('2') {
+                            cur_trans_unit.state = attributes
+                                .get("state")
+                                .map(|v| TargetState::from(v.as_str()));
+                            cur_trans_unit.state_qualifier =
+                                attributes.get("state-qualifier").cloned();
+                            cur_trans_unit.span.state_tag = Some(tag);
                         }
                     }
-                    b"alt-trans" => match reader.read_to_end(e.name()) {
-                        Ok(_range) => (),
-                        Err(e) => eprintln!(
-                            "Error parsing alt-trans tag at {}: {:?}",
-                            reader.buffer_position(),
-                            e
-                        ),
-                    },
+                    b"alt-trans" => {
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        cur_alt_trans = Some(AltTrans {
+                            match_quality: attributes.get("match-quality").cloned(),
+                            origin: attributes.get("origin").cloned(),
+                            tool_id: attributes
+                                .get("tool-id")
+                                .or_else(|| attributes.get("tool"))
+                                .cloned(),
+                            ..Default::default()
+                        });
+                    }
+                    b"ignorable" => {
+                        reader
+                            .read_to_end(e.name())
+                            .map_err(|err| Error::xml(reader.buffer_position(), err))?;
+                    }
+                    _ => (),
+                },
+                Ok(Event::Empty(e)) => match e.name().as_ref() {
+                    b"target" if cur_alt_trans.is_none() => {
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        let tag = event_start..reader.buffer_position();
+
+                        cur_trans_unit.span.target_tag = Some(tag.clone());
+                        cur_trans_unit.target = Some(Vec::new());
+                        if !self.version.starts_with('2') {
+                            cur_trans_unit.state = attributes
+                                .get("state")
+                                .map(|v| TargetState::from(v.as_str()));
+                            cur_trans_unit.state_qualifier =
+                                attributes.get("state-qualifier").cloned();
+                            cur_trans_unit.span.state_tag = Some(tag);
+                        }
+                    }
+                    b"sdl:seg" => {
+                        let attributes = crate::get_attributes(&reader, &e)?;
+                        let sdl_seg = SdlSegment::from_attributes(&attributes);
+                        cur_trans_unit
+                            .span
+                            .record_sdl_seg(event_start..reader.buffer_position());
+                        cur_trans_unit.sdl_segs.push(sdl_seg);
+                    }
                     _ => (),
                 },
                 Ok(Event::End(e)) => match e.name().as_ref() {
+                    b"alt-trans" => {
+                        if let Some(alt_trans) = cur_alt_trans.take() {
+                            cur_trans_unit.alt_trans.push(alt_trans);
+                        }
+                    }
                     b"file" => {
-                        if cur_xfile.trans_units.len() != 0 {
+                        if !cur_xfile.trans_units.is_empty() {
                             self.xfiles.push(cur_xfile);
                         }
                         cur_xfile = XFile::default();
                     }
-                    b"trans-unit" => {
-                        if cur_trans_unit.source.len() != 0 && cur_trans_unit.target.len() != 0 {
-                            cur_xfile.trans_units.push(cur_trans_unit)
+                    b"unit" => in_unit = false,
+                    b"trans-unit" | b"segment" => {
+                        if e.name().as_ref() == b"trans-unit" {
+                            in_unit = false;
                         }
+                        cur_trans_unit.span.record_original(
+                            content,
+                            cur_trans_unit.target.as_deref().unwrap_or_default(),
+                            &cur_trans_unit.state,
+                            &cur_trans_unit.state_qualifier,
+                        );
+                        cur_xfile.trans_units.push(cur_trans_unit);
                         cur_trans_unit = TransUnit::default();
                     }
                     _ => (),
@@ -171,27 +934,95 @@ impl XliffFile {
             }
             buf.clear()
         }
+
+        return Ok(());
     }
 }
 
+fn open_archive(path: &str) -> Result<ZipArchive<File>> {
+    let zipped_file = File::open(path).map_err(|e| Error::Io {
+        path: path.to_owned(),
+        source: e,
+    })?;
+
+    return ZipArchive::new(zipped_file).map_err(|e| Error::Zip {
+        path: path.to_owned(),
+        source: e,
+    });
+}
+
+fn read_zip_entry(archive: &mut ZipArchive<File>, path: &str, name: &str) -> Result<String> {
+    let mut file = archive.by_name(name).map_err(|_| Error::ZipEntryNotFound {
+        path: path.to_owned(),
+        entry: name.to_owned(),
+    })?;
+
+    let mut contents = String::new();
+
+    file.read_to_string(&mut contents).map_err(|e| Error::Io {
+        path: path.to_owned(),
+        source: e,
+    })?;
+
+    return Ok(contents);
+}
+
 impl SearchInFile for XliffFile {
     fn search_in_file(
         &self,
-        include_tags: bool,
-        matcher: &Box<dyn SearchString>,
+        options: &SearchOptions,
+        matcher: &dyn SearchString,
     ) -> Vec<MatchResult> {
         let mut match_results = Vec::new();
 
-        for file in &self.xfiles {
-            search_in_transunits(&file.trans_units, include_tags, matcher, &mut match_results)
+        for (file_index, file) in self.xfiles.iter().enumerate() {
+            search_in_transunits(
+                &self.search_source(file_index, file),
+                &file.trans_units,
+                options,
+                matcher,
+                &mut match_results,
+            )
         }
 
         return match_results;
     }
+
+    fn search_bilingual(
+        &self,
+        options: &SearchOptions,
+        query: &BilingualQuery,
+    ) -> Vec<BilingualMatch> {
+        let mut bilingual_matches = Vec::new();
+
+        for (file_index, file) in self.xfiles.iter().enumerate() {
+            search_bilingual_in_transunits(
+                &self.search_source(file_index, file),
+                &file.trans_units,
+                options,
+                query,
+                &mut bilingual_matches,
+            )
+        }
+
+        return bilingual_matches;
+    }
+}
+
+impl XliffFile {
+    fn search_source<'a>(&'a self, file_index: usize, file: &'a XFile) -> SearchSource<'a> {
+        return SearchSource {
+            path: &self.path,
+            format: self.format,
+            file_index,
+            source_language: &file.src_language,
+            target_language: &file.tgt_language,
+        };
+    }
 }
 
 impl GetMeta for XliffFile {
-    fn get_meta(&self) -> MetaInfo {
+    fn get_meta(&self) -> MetaInfo<'_> {
         let mut languages = HashMap::new();
 
         for f in &self.xfiles {
@@ -200,25 +1031,315 @@ impl GetMeta for XliffFile {
             let src_acc_len = languages.get(src_key).unwrap_or(&0).to_owned();
             let tgt_acc_len = languages.get(tgt_key).unwrap_or(&0).to_owned();
 
-            let len = f.trans_units.len();
-            languages.insert(src_key, len + src_acc_len);
-            languages.insert(tgt_key, len + tgt_acc_len); // how about non-translated?
+            // the target language only counts segments that have a translation
+            let counts = StatusCounts::from_units(&f.trans_units);
+            languages.insert(src_key, counts.total() + src_acc_len);
+            languages.insert(tgt_key, counts.translated + tgt_acc_len);
         }
 
-        return MetaInfo { languages };
+        return MetaInfo {
+            languages,
+            status: Some(StatusCounts::from_units(
+                self.xfiles.iter().flat_map(|f| &f.trans_units),
+            )),
+        };
     }
 
     fn get_filename(&self) -> String {
         return self.path.to_owned();
     }
+
+    fn get_format(&self) -> FileFormat {
+        return self.format;
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::SearchInFile;
+
     #[test]
     fn dummy_for_debug() {
-        let t = crate::xliff::XliffFile::new(&"./tests/approval.sdlxliff");
-        dbg!(t.xfiles);
-        assert!(1 != 2);
+        let t = crate::xliff::XliffFile::new("./tests/approval.sdlxliff").unwrap();
+        dbg!(&t.xfiles);
+        assert!(!t.xfiles.is_empty());
+    }
+
+    #[test]
+    fn malformed_xml_is_an_error() {
+        match crate::xliff::XliffFile::new("./tests/malformed.xliff") {
+            Err(crate::Error::Xml { position, .. }) => assert_eq!(position.line, 7),
+            other => panic!("expected an XML error, got {:?}", other.map(|_| ())),
+        }
+
+        assert!(matches!(
+            crate::xliff::XliffFile::new("./tests/missing.xliff"),
+            Err(crate::Error::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn parses_xliff_2() {
+        let t = crate::xliff::XliffFile::new("./tests/sample_v2.xlf").unwrap();
+        assert_eq!(t.version, "2.0");
+
+        let xfile = &t.xfiles[0];
+        assert_eq!(xfile.src_language, "en-us");
+        assert_eq!(xfile.tgt_language, "fr-fr");
+        assert_eq!(xfile.trans_units.len(), 3);
+
+        let first = &xfile.trans_units[0];
+        assert_eq!(first.id, "u1");
+        assert_eq!(first.segment_id.as_deref(), Some("s1"));
+        assert_eq!(xfile.trans_units[1].id, "u1");
+        assert_eq!(xfile.trans_units[1].segment_id.as_deref(), Some("s2"));
+        assert_eq!(xfile.trans_units[2].segment_id, None);
+
+        let found = t.search_in_file(&crate::SearchOptions::default(), &"hold".to_string());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].unit_id, "u1");
+        assert_eq!(found[0].segment_id.as_deref(), Some("s2"));
+        assert_eq!(
+            crate::extract_text(first.target.as_deref().unwrap(), false),
+            "Cliquez sur Enregistrer pour continuer."
+        );
+        assert_eq!(
+            crate::extract_text(&xfile.trans_units[1].source, false),
+            "Press  and hold."
+        );
+        assert_eq!(xfile.trans_units[2].translate, "no");
+    }
+
+    #[test]
+    fn parses_state_approval_and_notes() {
+        use crate::xliff::{SdlConfirmation, TargetState};
+
+        let t = crate::xliff::XliffFile::new("./tests/approval.sdlxliff").unwrap();
+        let units = &t.xfiles[0].trans_units;
+
+        assert_eq!(units[0].approved, Some(true));
+        assert!(units[0].is_approved());
+        assert_eq!(units[0].sdl_segs.len(), 2);
+        assert_eq!(
+            units[0].sdl_segs[0].conf,
+            Some(SdlConfirmation::ApprovedTranslation)
+        );
+        assert!(units[0].sdl_segs[1].locked);
+        assert_eq!(units[0].sdl_segs[1].percent, Some(100));
+        assert_eq!(units[0].sdl_segs[1].origin.as_deref(), Some("tm"));
+
+        let unit = &units[1];
+        assert_eq!(unit.state, Some(TargetState::NeedsReviewTranslation));
+        assert_eq!(unit.state_qualifier.as_deref(), Some("mt-suggestion"));
+        assert_eq!(unit.resname.as_deref(), Some("status_label"));
+        assert_eq!(unit.maxwidth, Some(60));
+        assert_eq!(unit.size_unit.as_deref(), Some("char"));
+        assert_eq!(unit.notes.len(), 1);
+        assert_eq!(unit.notes[0].text, "Check the term for \"approval\".");
+        assert_eq!(unit.notes[0].priority, Some(2));
+        assert_eq!(unit.sdl_segs[0].conf, Some(SdlConfirmation::Draft));
+        assert!(!unit.is_approved());
+
+        let v2 = crate::xliff::XliffFile::new("./tests/sample_v2.xlf").unwrap();
+        assert_eq!(
+            v2.xfiles[0].trans_units[0].state,
+            Some(TargetState::Translated)
+        );
+    }
+
+    #[test]
+    fn keeps_units_without_target() {
+        use crate::xliff::{StatusCounts, UnitStatus};
+        use crate::GetMeta;
+
+        let t = crate::xliff::XliffFile::new("./tests/approval.sdlxliff").unwrap();
+        let units = &t.xfiles[0].trans_units;
+        assert_eq!(units.len(), 3);
+        assert_eq!(units[2].id, "g7h8i9");
+        assert!(units[2].target.is_none());
+        assert_eq!(units[2].status(), UnitStatus::Locked);
+
+        // a1b2c3 holds two SDL segments, the second one locked
+        let meta = t.get_meta();
+        assert_eq!(meta.languages().get("en-us"), Some(&4));
+        assert_eq!(meta.languages().get("de-de"), Some(&2));
+        assert_eq!(
+            meta.status(),
+            Some(StatusCounts {
+                translated: 2,
+                untranslated: 0,
+                locked: 2
+            })
+        );
+
+        let mut untranslated = units[1].clone();
+        untranslated.target = Some(Vec::new());
+        assert_eq!(untranslated.status(), UnitStatus::Untranslated);
+
+        let matcher = "CONFIDENTIAL".to_string();
+        let mut options = crate::SearchOptions {
+            status: Some(UnitStatus::Translated),
+            ..Default::default()
+        };
+        assert!(t.search_in_file(&options, &matcher).is_empty());
+        options.status = Some(UnitStatus::Locked);
+        assert_eq!(t.search_in_file(&options, &matcher).len(), 1);
+    }
+
+    #[test]
+    fn splits_sdl_mrk_segments() {
+        use crate::xliff::SdlConfirmation;
+
+        let t = crate::xliff::XliffFile::new("./tests/approval.sdlxliff").unwrap();
+        let unit = &t.xfiles[0].trans_units[0];
+
+        assert!(unit.seg_source.is_some());
+        let segments = unit.segments();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1].mid, "2");
+        assert_eq!(
+            crate::extract_text(segments[1].source, false),
+            "It is ready."
+        );
+        assert_eq!(
+            crate::extract_text(segments[1].target.unwrap(), false),
+            "Es ist fertig."
+        );
+        assert_eq!(
+            segments[0].sdl.unwrap().conf,
+            Some(SdlConfirmation::ApprovedTranslation)
+        );
+        assert!(!unit.clone().set_segment_target("9", Vec::new()));
+
+        let matches = t.search_in_file(&crate::SearchOptions::default(), &"fertig".to_string());
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].text, "Es ist fertig.");
+        assert_eq!(matches[0].unit_id, "a1b2c3");
+        assert_eq!(matches[0].mid.as_deref(), Some("2"));
+        assert_eq!(matches[0].language, "de-de");
+        assert_eq!(
+            matches[0].other_languages["en-us"],
+            vec!["It is ready.".to_string()]
+        );
+    }
+
+    #[test]
+    fn reports_every_occurrence_on_both_sides() {
+        let t = crate::xliff::XliffFile::new("./tests/approval.sdlxliff").unwrap();
+        let options = crate::SearchOptions::default();
+        let matcher = "en".to_string();
+
+        let matches = t.search_in_file(&options, &matcher);
+        let segment = matches
+            .iter()
+            .filter(|m| m.mid.as_deref() == Some("1"))
+            .map(|m| (m.language.as_str(), m.occurrences.len()))
+            .collect::<Vec<_>>();
+        // "document" and "genehmigen ... Dokument"
+        assert_eq!(segment, vec![("en-us", 1), ("de-de", 3)]);
+
+        let target_only = crate::SearchOptions {
+            scope: crate::SearchScope::Target,
+            ..Default::default()
+        };
+        let targets = t.search_in_file(&target_only, &matcher);
+        assert!(targets.iter().all(|m| m.language == "de-de"));
+
+        let counts = t.count_in_file(&options, &matcher);
+        let hits = matches.iter().map(|m| m.occurrences.len()).sum::<usize>();
+        assert_eq!(counts.total, hits);
+        assert_eq!(counts.languages["en-us"] + counts.languages["de-de"], hits);
+    }
+
+    #[test]
+    fn locates_matches() {
+        let t = crate::xliff::XliffFile::new("./tests/approval.sdlxliff").unwrap();
+        let matches = t.search_in_file(
+            &crate::SearchOptions::default(),
+            &"Genehmigungsstatus".to_string(),
+        );
+
+        let m = &matches[0];
+        assert_eq!((m.file_index, m.unit_index, m.sn), (0, 1, Some(2)));
+        assert_eq!(m.occurrences[0].matched, "Genehmigungsstatus");
+        assert_eq!(m.occurrences[0].span, 4..22);
+        // the <target> content of d4e5f6
+        assert_eq!((m.position.line, m.position.column), (22, 82));
+
+        let json = serde_json::to_value(m).unwrap();
+        assert_eq!(json["format"], "xliff");
+        assert_eq!(json["occurrences"][0]["span"]["start"], 4);
+        assert_eq!(json["position"]["line"], 22);
+    }
+
+    #[test]
+    fn parses_memoq_metadata() {
+        use crate::xliff::{MemoqStatus, UnitStatus};
+
+        let t = crate::xliff::XliffFile::new("./tests/review.mqxliff").unwrap();
+        let units = &t.xfiles[0].trans_units;
+
+        let memoq = units[0].memoq.as_ref().unwrap();
+        assert_eq!(memoq.status, Some(MemoqStatus::ManuallyConfirmed));
+        assert_eq!(memoq.percent, Some(100));
+        assert!(!memoq.locked);
+        assert_eq!(memoq.last_changing_user.as_deref(), Some("akiko"));
+        assert_eq!(
+            memoq.last_changed_timestamp.as_deref(),
+            Some("2023-03-14T09:26:53Z")
+        );
+        assert_eq!(memoq.comments.len(), 1);
+        assert_eq!(memoq.comments[0].text, "Use the polite form.");
+        assert_eq!(memoq.comments[0].creator_user.as_deref(), Some("ken"));
+
+        assert_eq!(units[1].status(), UnitStatus::Locked);
+        assert_eq!(units[2].status(), UnitStatus::Untranslated);
+
+        let package =
+            crate::read_file_with_parser(std::path::Path::new("./tests/review.mqxlz")).unwrap();
+        assert_eq!(package.get_format(), crate::FileFormat::Mqxlz);
+        let package = package
+            .as_any()
+            .downcast_ref::<crate::xliff::XliffFile>()
+            .unwrap();
+        assert_eq!(
+            package.xfiles[0].trans_units[1]
+                .memoq
+                .as_ref()
+                .unwrap()
+                .status,
+            Some(MemoqStatus::PartiallyEdited)
+        );
+    }
+
+    #[test]
+    fn parses_alt_trans_candidates() {
+        let t = crate::xliff::XliffFile::new("./tests/alt_trans.xlf").unwrap();
+        let unit = &t.xfiles[0].trans_units[0];
+
+        assert_eq!(
+            crate::extract_text(unit.target.as_deref().unwrap(), false),
+            "Le compte est verrouillé."
+        );
+        assert_eq!(unit.alt_trans.len(), 2);
+        assert_eq!(unit.alt_trans[0].match_percent(), Some(85.0));
+        assert_eq!(unit.alt_trans[0].origin.as_deref(), Some("Main TM"));
+        assert_eq!(unit.alt_trans[1].tool_id.as_deref(), Some("mt-engine"));
+        assert_eq!(
+            crate::extract_text(&unit.alt_trans[1].target, false),
+            "Le compte a été bloqué."
+        );
+
+        let matcher = "bloqué".to_string();
+        let mut options = crate::SearchOptions::default();
+        assert!(t.search_in_file(&options, &matcher).is_empty());
+
+        options.include_alt_trans = true;
+        let found = t.search_in_file(&options, &matcher);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].alt_trans, Some(1));
+        assert_eq!(found[0].language, "fr-ca");
+        assert_eq!(found[0].position.line, 13);
+        assert_eq!(found[0].position.column, 36);
     }
 }
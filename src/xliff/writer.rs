@@ -0,0 +1,625 @@
+use super::{
+    fill_segments, mrk_segments, open_archive, read_zip_entry, SdlSegment, TargetState, TransUnit,
+    XliffFile,
+};
+use crate::{segs_to_xml, Error, FileFormat, Result, SegNode};
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::reader::Reader;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::prelude::*;
+use std::ops::Range;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Where a unit's target lives in the original document. Recorded while
+/// parsing so the writer can splice edits into otherwise untouched markup.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct TargetSpan {
+    /// Start tag carrying the state attributes: `<target>` in 1.2,
+    /// `<segment>` in 2.x.
+    pub(super) state_tag: Option<Range<usize>>,
+    /// `<target>` start tag, or the whole element when it is self-closing.
+    pub(super) target_tag: Option<Range<usize>>,
+    pub(super) target_content: Option<Range<usize>>,
+    /// Where a new `<target>` goes when the unit has none: right after
+    /// `</seg-source>`, or `</source>` when there is no `<seg-source>`.
+    insert_at: usize,
+    indent: String,
+    target_hash: u64,
+    state: Option<TargetState>,
+    state_qualifier: Option<String>,
+    /// `<sdl:seg>` elements; what they held is parsed again when writing.
+    sdl_segs: Vec<Range<usize>>,
+    /// Content of each `<mrk mtype="seg">` of the target with the hash of
+    /// what it held, so an edited segment is spliced in on its own.
+    segment_targets: Vec<(Range<usize>, u64)>,
+    /// Hash of the target with its segments emptied.
+    skeleton_hash: u64,
+}
+
+impl TargetSpan {
+    pub(super) fn record_source(&mut self, raw_content: &str, start: usize, end: usize) {
+        self.insert_at = end;
+
+        let line_start = raw_content[..start].rfind('\n').map(|p| p + 1).unwrap_or(0);
+        let indent = &raw_content[line_start..start];
+        if indent.chars().all(char::is_whitespace) {
+            self.indent = indent.to_owned();
+        }
+    }
+
+    pub(super) fn record_seg_source(&mut self, end: usize) {
+        self.insert_at = end;
+    }
+
+    pub(super) fn record_sdl_seg(&mut self, tag: Range<usize>) {
+        self.sdl_segs.push(tag);
+    }
+
+    pub(super) fn record_original(
+        &mut self,
+        raw_content: &str,
+        target: &[Box<SegNode>],
+        state: &Option<TargetState>,
+        state_qualifier: &Option<String>,
+    ) {
+        self.target_hash = hash_segs(target);
+        self.state = state.to_owned();
+        self.state_qualifier = state_qualifier.to_owned();
+
+        let Some(content) = &self.target_content else {
+            return;
+        };
+        let ranges = segment_ranges(raw_content, content.clone());
+        let mut segments = Vec::new();
+        mrk_segments(target, &mut segments);
+        if !ranges.is_empty() && ranges.len() == segments.len() {
+            self.segment_targets = ranges
+                .into_iter()
+                .zip(segments)
+                .map(|(range, (_, segment))| (range, hash_segs(segment)))
+                .collect();
+            self.skeleton_hash = skeleton_hash(target);
+        }
+    }
+}
+
+/// Content ranges of the outermost `<mrk mtype="seg">` elements in
+/// `content`.
+fn segment_ranges(raw_content: &str, content: Range<usize>) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    if !raw_content[content.clone()].contains("<mrk") {
+        return ranges;
+    }
+
+    let mut reader = Reader::from_str(&raw_content[content.clone()]);
+    // for each open element, where its content starts if it is a segment
+    let mut open: Vec<Option<usize>> = Vec::new();
+    loop {
+        let event_start = reader.buffer_position();
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                let is_segment = e.name().as_ref() == b"mrk"
+                    && e.try_get_attribute("mtype")
+                        .ok()
+                        .flatten()
+                        .is_some_and(|mtype| mtype.value.as_ref() == b"seg")
+                    && open.iter().all(Option::is_none);
+                open.push(is_segment.then(|| content.start + reader.buffer_position()));
+            }
+            Ok(Event::End(_)) => {
+                if let Some(Some(start)) = open.pop() {
+                    ranges.push(start..content.start + event_start);
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => (),
+        }
+    }
+
+    return ranges;
+}
+
+fn skeleton_hash(target: &[Box<SegNode>]) -> u64 {
+    let mut skeleton = target.to_vec();
+    fill_segments(&mut skeleton, &mut |_| Vec::new());
+    return hash_segs(&skeleton);
+}
+
+pub(super) fn hash_content(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    return hasher.finish();
+}
+
+fn hash_segs(segs: &[Box<SegNode>]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    segs_to_xml(segs).hash(&mut hasher);
+    return hasher.finish();
+}
+
+impl XliffFile {
+    /// Renders the document with edited targets and states spliced into the
+    /// original markup. Everything outside changed units is copied byte for
+    /// byte.
+    pub fn render(&self) -> Result<String> {
+        if self.format == FileFormat::Sdlppx {
+            return Err(Error::WriteUnsupported {
+                path: self.path.to_owned(),
+                format: self.format,
+            });
+        }
+
+        let raw_content = self.read_original()?;
+        let is_v2 = self.version.starts_with('2');
+        let mut edits = Vec::new();
+
+        for unit in self.xfiles.iter().flat_map(|f| &f.trans_units) {
+            unit_edits(&raw_content, unit, is_v2, &mut edits);
+        }
+        edits.sort_by_key(|(range, _)| range.start);
+
+        let mut rendered = String::with_capacity(raw_content.len());
+        let mut cursor = 0;
+        for (range, text) in edits {
+            rendered.push_str(&raw_content[cursor..range.start]);
+            rendered.push_str(&text);
+            cursor = range.end;
+        }
+        rendered.push_str(&raw_content[cursor..]);
+
+        return Ok(rendered);
+    }
+
+    /// Reads the document again from disk, making sure it is still the one
+    /// the recorded spans point into.
+    fn read_original(&self) -> Result<String> {
+        let raw_content = match &self.entry {
+            Some(entry) => read_zip_entry(&mut open_archive(&self.path)?, &self.path, entry)?,
+            None => crate::read_to_string(&self.path)?,
+        };
+
+        if hash_content(&raw_content) != self.original_hash {
+            return Err(Error::SourceChanged {
+                path: self.path.to_owned(),
+            });
+        }
+
+        return Ok(raw_content);
+    }
+
+    /// Writes the rendered document to `path`. Zipped files are rewritten
+    /// with every other archive entry copied unchanged. After saving over the
+    /// original, parse it again before saving further edits.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let rendered = self.render()?;
+
+        let entry = match &self.entry {
+            Some(entry) => entry,
+            None => {
+                return std::fs::write(path, rendered).map_err(|e| Error::Write {
+                    path: path.to_owned(),
+                    source: e,
+                })
+            }
+        };
+
+        let io_error = |e| Error::Write {
+            path: path.to_owned(),
+            source: e,
+        };
+        let zip_error = |e| Error::Zip {
+            path: path.to_owned(),
+            source: e,
+        };
+
+        let mut archive = open_archive(&self.path)?;
+        let tmp_path = format!("{}.tmp", path);
+        let mut zip_writer = ZipWriter::new(File::create(&tmp_path).map_err(io_error)?);
+
+        for i in 0..archive.len() {
+            let file = archive.by_index_raw(i).map_err(zip_error)?;
+            if file.name() == entry {
+                drop(file);
+                let options =
+                    FileOptions::default().compression_method(CompressionMethod::Deflated);
+                zip_writer.start_file(entry, options).map_err(zip_error)?;
+                zip_writer
+                    .write_all(rendered.as_bytes())
+                    .map_err(io_error)?;
+            } else {
+                zip_writer.raw_copy_file(file).map_err(zip_error)?;
+            }
+        }
+
+        zip_writer.finish().map_err(zip_error)?;
+        std::fs::rename(&tmp_path, path).map_err(io_error)?;
+
+        return Ok(());
+    }
+}
+
+fn unit_edits(
+    raw_content: &str,
+    unit: &TransUnit,
+    is_v2: bool,
+    edits: &mut Vec<(Range<usize>, String)>,
+) {
+    let span = &unit.span;
+    sdl_seg_edits(raw_content, unit, edits);
+
+    let target = unit.target.as_deref().unwrap_or_default();
+    let target_changed = hash_segs(target) != span.target_hash;
+    let state_changed = unit.state != span.state || unit.state_qualifier != span.state_qualifier;
+
+    if !target_changed && !state_changed {
+        return;
+    }
+
+    let qualifier_name = if is_v2 { "subState" } else { "state-qualifier" };
+    let set_state = |tag: &str| {
+        let tag = set_attribute(tag, "state", unit.state.as_ref().map(|s| s.as_str()));
+        return set_attribute(&tag, qualifier_name, unit.state_qualifier.as_deref());
+    };
+    let state_on_target = state_changed && !is_v2;
+
+    match (&span.target_tag, &span.target_content) {
+        (Some(tag), Some(content)) => {
+            if state_on_target {
+                edits.push((tag.clone(), set_state(&raw_content[tag.clone()])));
+            }
+            if target_changed && !segment_edits(unit, target, edits) {
+                edits.push((content.clone(), segs_to_xml(target)));
+            }
+        }
+        (Some(tag), None) => {
+            let mut element = raw_content[tag.clone()].to_owned();
+            if state_on_target {
+                element = set_state(&element);
+            }
+            if target_changed {
+                let name = element_name(&element).to_owned();
+                let start_tag = element
+                    .trim_end_matches('>')
+                    .trim_end_matches('/')
+                    .trim_end();
+                element = format!("{}>{}</{}>", start_tag, segs_to_xml(target), name);
+            }
+            edits.push((tag.clone(), element));
+        }
+        (None, _) => {
+            if span.insert_at != 0 && (target_changed || state_on_target) {
+                let start_tag = if is_v2 {
+                    "<target>".to_string()
+                } else {
+                    set_state("<target>")
+                };
+                edits.push((
+                    span.insert_at..span.insert_at,
+                    format!(
+                        "\n{}{}{}</target>",
+                        span.indent,
+                        start_tag,
+                        segs_to_xml(target)
+                    ),
+                ));
+            }
+        }
+    }
+
+    if state_changed && is_v2 {
+        if let Some(tag) = &span.state_tag {
+            edits.push((tag.clone(), set_state(&raw_content[tag.clone()])));
+        }
+    }
+}
+
+/// Splices the edited segments of a target whose markup around them is
+/// unchanged. Returns false when the target has to be written whole.
+fn segment_edits(
+    unit: &TransUnit,
+    target: &[Box<SegNode>],
+    edits: &mut Vec<(Range<usize>, String)>,
+) -> bool {
+    let span = &unit.span;
+    let mut segments = Vec::new();
+    mrk_segments(target, &mut segments);
+    if span.segment_targets.is_empty()
+        || segments.len() != span.segment_targets.len()
+        || skeleton_hash(target) != span.skeleton_hash
+    {
+        return false;
+    }
+
+    for ((range, hash), (_, segment)) in span.segment_targets.iter().zip(segments) {
+        if hash_segs(segment) != *hash {
+            edits.push((range.clone(), segs_to_xml(segment)));
+        }
+    }
+    return true;
+}
+
+/// Rewrites the attributes of edited `<sdl:seg>` definitions.
+fn sdl_seg_edits(raw_content: &str, unit: &TransUnit, edits: &mut Vec<(Range<usize>, String)>) {
+    for tag in &unit.span.sdl_segs {
+        let original = SdlSegment::from_attributes(&tag_attributes(&raw_content[tag.clone()]));
+        let sdl_seg = unit.sdl_segs.iter().find(|s| s.id == original.id);
+        let Some(sdl_seg) = sdl_seg.filter(|s| **s != original) else {
+            continue;
+        };
+
+        let mut element = raw_content[tag.clone()].to_owned();
+        if sdl_seg.conf != original.conf {
+            let conf = sdl_seg.conf.as_ref().map(|c| c.as_str());
+            element = set_attribute(&element, "conf", conf);
+        }
+        if sdl_seg.locked != original.locked {
+            element = set_attribute(&element, "locked", sdl_seg.locked.then_some("true"));
+        }
+        if sdl_seg.percent != original.percent {
+            let percent = sdl_seg.percent.map(|p| p.to_string());
+            element = set_attribute(&element, "percent", percent.as_deref());
+        }
+        if sdl_seg.origin != original.origin {
+            element = set_attribute(&element, "origin", sdl_seg.origin.as_deref());
+        }
+        if sdl_seg.origin_system != original.origin_system {
+            let origin_system = sdl_seg.origin_system.as_deref();
+            element = set_attribute(&element, "origin-system", origin_system);
+        }
+        edits.push((tag.clone(), element));
+    }
+}
+
+/// The attributes of a raw start tag, empty when it does not parse.
+fn tag_attributes(tag: &str) -> HashMap<String, String> {
+    let reader = &mut Reader::from_str(tag);
+    return match reader.read_event() {
+        Ok(Event::Start(e)) | Ok(Event::Empty(e)) => {
+            crate::get_attributes(reader, &e).unwrap_or_default()
+        }
+        _ => HashMap::new(),
+    };
+}
+
+fn element_name(tag: &str) -> &str {
+    return tag
+        .trim_start_matches('<')
+        .split(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .next()
+        .unwrap_or_default();
+}
+
+/// Sets, replaces or (with `None`) removes one attribute in a raw start tag,
+/// leaving the other attributes, their order and their quoting untouched.
+fn set_attribute(tag: &str, name: &str, value: Option<&str>) -> String {
+    let bytes = tag.as_bytes();
+    let len = bytes.len();
+    let is_space = |b: u8| b.is_ascii_whitespace();
+
+    let mut i = 1;
+    while i < len && !is_space(bytes[i]) && bytes[i] != b'>' && bytes[i] != b'/' {
+        i += 1;
+    }
+    let mut last_attribute_end = i;
+
+    loop {
+        let attribute_start = i;
+        while i < len && is_space(bytes[i]) {
+            i += 1;
+        }
+        if i >= len || bytes[i] == b'>' || bytes[i] == b'/' {
+            break;
+        }
+
+        let name_start = i;
+        while i < len && !is_space(bytes[i]) && bytes[i] != b'=' && bytes[i] != b'>' {
+            i += 1;
+        }
+        let attribute_name = &tag[name_start..i];
+
+        while i < len && (is_space(bytes[i]) || bytes[i] == b'=') {
+            i += 1;
+        }
+        let quote = bytes.get(i).copied().unwrap_or(b'"');
+        i += 1;
+        let value_start = i.min(len);
+        while i < len && bytes[i] != quote {
+            i += 1;
+        }
+        let value_end = i.min(len);
+        i = (i + 1).min(len);
+
+        if attribute_name == name {
+            return match value {
+                Some(v) => format!("{}{}{}", &tag[..value_start], escape(v), &tag[value_end..]),
+                None => format!("{}{}", &tag[..attribute_start], &tag[i..]),
+            };
+        }
+        last_attribute_end = i;
+    }
+
+    return match value {
+        Some(v) => format!(
+            "{} {}=\"{}\"{}",
+            &tag[..last_attribute_end],
+            name,
+            escape(v),
+            &tag[last_attribute_end..]
+        ),
+        None => tag.to_owned(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::set_attribute;
+    use crate::xliff::{SdlConfirmation, TargetState, XliffFile};
+    use crate::SegNode;
+
+    #[test]
+    fn set_attribute_keeps_order_and_quotes() {
+        let tag = "<target xml:lang='de-DE' state=\"new\" >";
+        assert_eq!(
+            set_attribute(tag, "state", Some("translated")),
+            "<target xml:lang='de-DE' state=\"translated\" >"
+        );
+        assert_eq!(
+            set_attribute(tag, "state", None),
+            "<target xml:lang='de-DE' >"
+        );
+        assert_eq!(
+            set_attribute("<target/>", "state", Some("final")),
+            "<target state=\"final\"/>"
+        );
+    }
+
+    #[test]
+    fn unchanged_document_renders_identically() {
+        let t = XliffFile::new("./tests/approval.sdlxliff").unwrap();
+        assert_eq!(
+            t.render().unwrap(),
+            crate::read_to_string("./tests/approval.sdlxliff").unwrap()
+        );
+    }
+
+    #[test]
+    fn edits_only_touch_changed_targets() {
+        let original = crate::read_to_string("./tests/sample_v2.xlf").unwrap();
+        let mut t = XliffFile::new("./tests/sample_v2.xlf").unwrap();
+
+        let unit = &mut t.xfiles[0].trans_units[1];
+        unit.target = Some(vec![Box::new(SegNode::Text(
+            "Appuyez <& maintenez".to_string(),
+        ))]);
+        unit.state = Some(TargetState::Final);
+
+        let rendered = t.render().unwrap();
+        let expected = original
+            .replace(
+                "<segment id=\"s2\" state=\"initial\">",
+                "<segment id=\"s2\" state=\"final\">",
+            )
+            .replace(
+                "<target>Appuyez sur <ph id=\"2\"/> et <sc id=\"3\"/>maintenez<ec startRef=\"3\"/>.</target>",
+                "<target>Appuyez &lt;&amp; maintenez</target>",
+            );
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn inserts_missing_target_after_source() {
+        let mut t = XliffFile::new("./tests/approval.sdlxliff").unwrap();
+        t.xfiles[0].trans_units[2].target =
+            Some(vec![Box::new(SegNode::Text("VERTRAULICH".to_string()))]);
+
+        let rendered = t.render().unwrap();
+        assert!(rendered.contains(
+            "<source>CONFIDENTIAL</source>\n        <target>VERTRAULICH</target>\n      </trans-unit>"
+        ));
+    }
+
+    #[test]
+    fn inserts_missing_target_after_seg_source() {
+        let mut t = XliffFile::new("./tests/untranslated.sdlxliff").unwrap();
+        let target = vec![Box::new(SegNode::Text(
+            "Speichern Sie die Datei.".to_string(),
+        ))];
+        assert!(t.xfiles[0].trans_units[0].set_segment_target("1", target));
+
+        let rendered = t.render().unwrap();
+        assert!(rendered.contains(
+            "<seg-source><mrk mtype=\"seg\" mid=\"1\">Save the file.</mrk></seg-source>\n        \
+             <target><mrk mid=\"1\" mtype=\"seg\">Speichern Sie die Datei.</mrk></target>\n        \
+             <sdl:seg-defs>"
+        ));
+    }
+
+    #[test]
+    fn writes_back_sdl_segments() {
+        let out = std::env::temp_dir().join("translation-xml-parser-segments.sdlxliff");
+        let out = out.to_str().unwrap();
+
+        let mut t = XliffFile::new("./tests/approval.sdlxliff").unwrap();
+        let unit = &mut t.xfiles[0].trans_units[0];
+        let target = vec![Box::new(SegNode::Text("Es ist jetzt fertig.".to_string()))];
+        assert!(unit.set_segment_target("2", target));
+        let sdl = &mut unit.sdl_segs[1];
+        sdl.conf = Some(SdlConfirmation::ApprovedTranslation);
+        sdl.origin = Some("interactive".to_string());
+        sdl.percent = None;
+        t.save(out).unwrap();
+
+        // only the content of segment 2 and its definition change; the
+        // other segment keeps its bytes
+        let rendered = crate::read_to_string(out).unwrap();
+        let original = crate::read_to_string("./tests/approval.sdlxliff").unwrap();
+        let expected = original
+            .replace(
+                "<mrk mtype=\"seg\" mid=\"2\">Es ist fertig.</mrk>",
+                "<mrk mtype=\"seg\" mid=\"2\">Es ist jetzt fertig.</mrk>",
+            )
+            .replace(
+                "<sdl:seg id=\"2\" conf=\"Translated\" origin=\"tm\" percent=\"100\" locked=\"true\" />",
+                "<sdl:seg id=\"2\" conf=\"ApprovedTranslation\" origin=\"interactive\" locked=\"true\" />",
+            );
+        assert_ne!(expected, original);
+        assert_eq!(rendered, expected);
+
+        let reread = XliffFile::new(out).unwrap();
+        let segments = reread.xfiles[0].trans_units[0].segments();
+        let text = |i: usize| crate::extract_text(segments[i].target.unwrap(), false);
+        assert_eq!(text(0), "Bitte genehmigen Sie das Dokument.");
+        assert_eq!(text(1), "Es ist jetzt fertig.");
+        assert_eq!(
+            segments[1].sdl.unwrap().conf,
+            Some(SdlConfirmation::ApprovedTranslation)
+        );
+    }
+
+    #[test]
+    fn refuses_to_render_a_changed_source() {
+        let path = std::env::temp_dir().join("translation-xml-parser-changed.xlf");
+        let path = path.to_str().unwrap();
+        std::fs::copy("./tests/sample_v2.xlf", path).unwrap();
+
+        let t = XliffFile::new(path).unwrap();
+        assert!(t.render().is_ok());
+
+        std::fs::write(path, "<xliff/>").unwrap();
+        assert!(matches!(
+            t.render(),
+            Err(crate::Error::SourceChanged { .. })
+        ));
+    }
+
+    #[test]
+    fn zipped_round_trip() {
+        let out = std::env::temp_dir().join("translation-xml-parser-round-trip.xlz");
+        let out = out.to_str().unwrap();
+
+        let mut t = XliffFile::new_zipped("./tests/approval.xlz", "content.xlf").unwrap();
+        t.xfiles[0].trans_units[1].target = Some(vec![Box::new(SegNode::Text("Neu".to_string()))]);
+        t.save(out).unwrap();
+
+        let reread = XliffFile::new_zipped(out, "content.xlf").unwrap();
+        assert_eq!(
+            crate::extract_text(
+                reread.xfiles[0].trans_units[1].target.as_deref().unwrap(),
+                false
+            ),
+            "Neu"
+        );
+        assert_eq!(
+            reread.xfiles[0].trans_units[0].target,
+            t.xfiles[0].trans_units[0].target
+        );
+
+        let mut archive = super::open_archive(out).unwrap();
+        assert!(archive.by_name("skeleton.skl").is_ok());
+    }
+}
@@ -1,12 +1,14 @@
+use crate::bilingual::search_bilingual_in_transunits;
 use crate::{
-    search_in_transunits, GetMeta, MatchResult, MetaInfo, SearchInFile, SearchString, SegNode,
+    search_in_transunits, BilingualMatch, BilingualQuery, Error, FileFormat, GetMeta, MatchResult,
+    MetaInfo, Position, Result, SearchInFile, SearchOptions, SearchSource, SearchString, SegNode,
 };
-use std::{collections::HashMap, fs::File, io::BufReader, process::exit};
+use std::{collections::HashMap, fs::File, io::BufReader};
 
 use calamine::{open_workbook, Reader, Xlsx};
 use quick_xml::Reader as XML_Reader;
 
-use crate::xliff::TransUnit;
+use crate::xliff::{StatusCounts, TransUnit};
 
 pub struct TranslationXlsx {
     pub path: String,
@@ -17,14 +19,11 @@ pub struct TranslationXlsx {
 }
 
 impl TranslationXlsx {
-    pub fn new(path: &str) -> TranslationXlsx {
-        let workbook: Xlsx<_> = match open_workbook(path) {
-            Ok(workbook) => workbook,
-            Err(e) => {
-                eprintln!("Failed to open xlsx file {} {:?}", path, e);
-                exit(1);
-            }
-        };
+    pub fn new(path: &str) -> Result<TranslationXlsx> {
+        let workbook: Xlsx<_> = open_workbook(path).map_err(|e| Error::Xlsx {
+            path: path.to_owned(),
+            source: e,
+        })?;
 
         let mut translation_xlsx = TranslationXlsx {
             path: path.to_owned(),
@@ -33,39 +32,39 @@ impl TranslationXlsx {
             src_language: "".to_string(),
             tgt_language: "".to_string(),
         };
-        translation_xlsx.parse();
-        return translation_xlsx;
+        translation_xlsx.parse()?;
+        return Ok(translation_xlsx);
     }
 
-    fn parse(&mut self) {
+    fn parse(&mut self) -> Result<()> {
         let all_worksheets = self.xlsx.worksheets();
 
-        if let Some(first_sheet) = all_worksheets.get(0) {
-            let mut sn = 0;
-
+        if let Some(first_sheet) = all_worksheets.first() {
             let mut cur_trans_unit;
 
-            println!("Checking {} sheet: {}", self.path, &first_sheet.0);
-
             let mut trans_unit_rows = first_sheet.1.rows();
 
             let header = trans_unit_rows.next().unwrap_or_default();
-            self.src_language = header[1]
-                .get_string()
-                .unwrap_or_default()
-                .to_string()
-                .to_lowercase();
-            self.tgt_language = header[2]
-                .get_string()
-                .unwrap_or_default()
-                .to_string()
-                .to_lowercase();
+            let language = |column: usize| {
+                header
+                    .get(column)
+                    .and_then(|cell| cell.get_string())
+                    .filter(|language| !language.is_empty())
+                    .map(|language| language.to_lowercase())
+                    .ok_or_else(|| Error::LanguageColumnsNotFound {
+                        path: self.path.to_owned(),
+                    })
+            };
+            self.src_language = language(1)?;
+            self.tgt_language = language(2)?;
 
             let mut buffer = Vec::new();
+            let mut sn = 0;
 
-            for r in trans_unit_rows {
+            // the header is row 1
+            for (row, r) in (2..).zip(trans_unit_rows) {
                 let id = r
-                    .get(0)
+                    .first()
                     .unwrap_or(&calamine::DataType::Empty {})
                     .to_string();
                 let source_value = r
@@ -76,56 +75,168 @@ impl TranslationXlsx {
                     .get(2)
                     .unwrap_or(&calamine::DataType::Empty {})
                     .to_string();
-
-                let mut source_reader = XML_Reader::from_str(&source_value);
-                let mut target_reader = XML_Reader::from_str(&target_value);
-
-                let source = SegNode::parse_inline(&mut source_reader, &mut buffer);
-                let target = SegNode::parse_inline(&mut target_reader, &mut buffer);
+                if id.is_empty() && source_value.is_empty() && target_value.is_empty() {
+                    continue;
+                }
                 sn += 1;
 
+                let source = parse_cell(&source_value, &mut buffer);
+                let target = parse_cell(&target_value, &mut buffer);
+                // an empty cell means the row has not been translated yet
+                let target = if target_value.is_empty() {
+                    None
+                } else {
+                    Some(target)
+                };
+
                 cur_trans_unit = TransUnit {
                     id,
                     sn,
                     source,
                     target,
                     translate: "yes".to_string(),
+                    source_position: Position {
+                        offset: 0,
+                        line: row,
+                        column: 2,
+                    },
+                    target_position: Position {
+                        offset: 0,
+                        line: row,
+                        column: 3,
+                    },
+                    ..Default::default()
                 };
-                if cur_trans_unit.id != "" {
-                    self.trans_units.push(cur_trans_unit)
-                }
+                self.trans_units.push(cur_trans_unit);
             }
         } else {
-            eprintln!("First worksheet not found!");
+            return Err(Error::WorksheetNotFound {
+                path: self.path.to_owned(),
+            });
         }
+
+        return Ok(());
+    }
+}
+
+/// Reads a cell as inline markup. Cells that are not well-formed markup,
+/// like `R&D` or `a < b`, are kept as plain text.
+#[allow(clippy::vec_box)]
+fn parse_cell(value: &str, buffer: &mut Vec<u8>) -> Vec<Box<SegNode>> {
+    let mut reader = XML_Reader::from_str(value);
+    buffer.clear();
+
+    match SegNode::parse_inline(&mut reader, buffer) {
+        // a stray end tag stops parsing early
+        Ok(segs) if reader.buffer_position() >= value.len() => segs,
+        _ => vec![Box::new(SegNode::Text(value.to_owned()))],
+    }
+}
+
+impl TranslationXlsx {
+    fn search_source(&self) -> SearchSource<'_> {
+        return SearchSource {
+            path: &self.path,
+            format: FileFormat::Xlsx,
+            file_index: 0,
+            source_language: &self.src_language,
+            target_language: &self.tgt_language,
+        };
     }
 }
 
 impl SearchInFile for TranslationXlsx {
     fn search_in_file(
         &self,
-        include_tags: bool,
-        matcher: &Box<dyn SearchString>,
+        options: &SearchOptions,
+        matcher: &dyn SearchString,
     ) -> Vec<MatchResult> {
         let mut match_results = Vec::new();
 
-        search_in_transunits(&self.trans_units, include_tags, matcher, &mut match_results);
+        search_in_transunits(
+            &self.search_source(),
+            &self.trans_units,
+            options,
+            matcher,
+            &mut match_results,
+        );
 
         return match_results;
     }
+
+    fn search_bilingual(
+        &self,
+        options: &SearchOptions,
+        query: &BilingualQuery,
+    ) -> Vec<BilingualMatch> {
+        let mut bilingual_matches = Vec::new();
+
+        search_bilingual_in_transunits(
+            &self.search_source(),
+            &self.trans_units,
+            options,
+            query,
+            &mut bilingual_matches,
+        );
+
+        return bilingual_matches;
+    }
 }
 
 impl GetMeta for TranslationXlsx {
-    fn get_meta(&self) -> MetaInfo {
+    fn get_meta(&self) -> MetaInfo<'_> {
+        let status = StatusCounts::from_units(&self.trans_units);
+
         return MetaInfo {
             languages: HashMap::from([
-                (self.src_language.as_str(), self.trans_units.len()),
-                (self.tgt_language.as_str(), self.trans_units.len()),
+                (self.src_language.as_str(), status.total()),
+                (self.tgt_language.as_str(), status.translated),
             ]),
+            status: Some(status),
         };
     }
 
     fn get_filename(&self) -> String {
         return self.path.to_owned();
     }
+
+    fn get_format(&self) -> FileFormat {
+        return FileFormat::Xlsx;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TranslationXlsx;
+    use crate::{extract_text, Error, GetMeta, SegNode};
+
+    #[test]
+    fn keeps_malformed_cells_as_text() {
+        let t = TranslationXlsx::new("./tests/glossary.xlsx").unwrap();
+        assert_eq!(
+            (t.src_language.as_str(), t.tgt_language.as_str()),
+            ("en-us", "zh-cn")
+        );
+
+        let texts = t
+            .trans_units
+            .iter()
+            .map(|tu| extract_text(&tu.source, false))
+            .collect::<Vec<_>>();
+        // the blank row 5 is no unit
+        assert_eq!(texts, vec!["R&D budget", "Keep a < b", "Press OK", "Save"]);
+        assert_eq!(t.trans_units[3].sn, 4);
+        assert_eq!(t.trans_units[3].source_position.line, 6);
+        assert_eq!(t.get_meta().status().unwrap().total(), 4);
+        assert!(matches!(
+            t.trans_units[2].source[1].as_ref(),
+            SegNode::OpenOrCloseNode { node_type, .. } if node_type == "g"
+        ));
+    }
+
+    #[test]
+    fn rejects_header_without_languages() {
+        let result = TranslationXlsx::new("./tests/no_languages.xlsx");
+        assert!(matches!(result, Err(Error::LanguageColumnsNotFound { .. })));
+    }
 }